@@ -2,6 +2,14 @@ use std::error::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // setup termina
-    chat::client::run().await
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() {
+        // setup termina
+        return chat::client::run().await;
+    }
+    if let Err(e) = chat::client::headless(args).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    Ok(())
 }
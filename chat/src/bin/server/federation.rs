@@ -0,0 +1,367 @@
+use crate::hooks::{sign, token, verify};
+use chat::{Data, Message};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest},
+    Request,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::RwLock, time::Duration};
+
+const WINDOW: u64 = 300;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Trust {
+    Blocked,
+    #[default]
+    Text,
+    Files,
+}
+impl Trust {
+    fn allows(self, data: &Data) -> bool {
+        match data {
+            Data::Text(_) => self != Trust::Blocked,
+            Data::File { .. } => self == Trust::Files,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Peer {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub trust: Trust,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    name: String,
+    #[serde(default)]
+    peers: HashMap<String, Peer>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Relay {
+    pub id: String,
+    pub sent: u64,
+    pub from: String,
+    pub src: u32,
+    pub dst: u32,
+    pub data: Data,
+}
+
+pub struct Signed {
+    peer: String,
+    signature: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Signed {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let headers = req.headers();
+        match (
+            headers.get_one("X-Chat-Peer"),
+            headers.get_one("X-Chat-Signature"),
+        ) {
+            (Some(peer), Some(signature)) => request::Outcome::Success(Signed {
+                peer: peer.to_string(),
+                signature: signature.to_string(),
+            }),
+            _ => request::Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+pub fn parse(addr: &str) -> Option<(u32, &str)> {
+    let (user, server) = addr.split_once('@')?;
+    if server.is_empty() {
+        return None;
+    }
+    Some((user.parse().ok()?, server))
+}
+
+pub struct Federation {
+    pub name: String,
+    peers: HashMap<String, Peer>,
+    aliases: RwLock<HashMap<(String, u32), u32>>,
+    remotes: RwLock<HashMap<u32, (String, u32)>>,
+    seen: RwLock<HashMap<String, HashMap<String, u64>>>,
+    client: reqwest::Client,
+}
+impl Federation {
+    pub fn new(name: String, peers: HashMap<String, Peer>) -> Self {
+        Self {
+            name,
+            peers,
+            aliases: RwLock::new(HashMap::new()),
+            remotes: RwLock::new(HashMap::new()),
+            seen: RwLock::new(HashMap::new()),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("can build client"),
+        }
+    }
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let config = serde_json::from_slice::<Config>(&fs::read(path)?)?;
+        Ok(Self::new(config.name, config.peers))
+    }
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("CHAT_FEDERATION") else {
+            return Self::new("local".to_string(), HashMap::new());
+        };
+        let federation = Self::load(&path).expect("can load federation");
+        println!(
+            "federation: {} <-> [{}]",
+            federation.name,
+            federation
+                .peers
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        federation
+    }
+    fn peer(&self, name: &str) -> Result<&Peer, Status> {
+        match self.peers.get(name) {
+            Some(peer) if peer.trust == Trust::Blocked => Err(Status::Forbidden),
+            Some(peer) => Ok(peer),
+            None => Err(Status::NotFound),
+        }
+    }
+    pub async fn alias(
+        &self,
+        server: &str,
+        user: u32,
+        next: impl FnOnce() -> u32,
+    ) -> Result<u32, Status> {
+        self.peer(server)?;
+        let key = (server.to_string(), user);
+        if let Some(id) = self.aliases.read().await.get(&key) {
+            return Ok(*id);
+        }
+        let mut aliases = self.aliases.write().await;
+        let id = *aliases.entry(key.clone()).or_insert_with(next);
+        self.remotes.write().await.insert(id, key);
+        Ok(id)
+    }
+    pub async fn remote(&self, id: u32) -> Option<(String, u32)> {
+        self.remotes.read().await.get(&id).cloned()
+    }
+    pub async fn relay(&self, server: &str, user: u32, msg: &Message) -> Result<(), Status> {
+        let peer = self.peer(server)?;
+        if !peer.trust.allows(&msg.data) {
+            return Err(Status::Forbidden);
+        }
+        let body = serde_json::to_vec(&Relay {
+            id: token(),
+            sent: now(),
+            from: self.name.clone(),
+            src: msg.id,
+            dst: user,
+            data: msg.data.clone(),
+        })
+        .expect("can serialize relay");
+        let resp = self
+            .client
+            .post(format!(
+                "{}/federation/relay",
+                peer.url.trim_end_matches('/')
+            ))
+            .header("Content-Type", "application/json")
+            .header("X-Chat-Peer", &self.name)
+            .header("X-Chat-Signature", sign(&peer.secret, &body))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| {
+                println!("relay to {server}: {e}");
+                Status::BadGateway
+            })?;
+        match Status::from_code(resp.status().as_u16()) {
+            Some(status) if status.class().is_success() => Ok(()),
+            Some(status) => Err(status),
+            None => Err(Status::BadGateway),
+        }
+    }
+    pub async fn accept(&self, signed: &Signed, body: &[u8]) -> Result<Relay, Status> {
+        let peer = self.peer(&signed.peer)?;
+        if !verify(&peer.secret, body, &signed.signature) {
+            return Err(Status::Unauthorized);
+        }
+        let relay = serde_json::from_slice::<Relay>(body).map_err(|_| Status::BadRequest)?;
+        if relay.from != signed.peer {
+            return Err(Status::Unauthorized);
+        }
+        if !peer.trust.allows(&relay.data) {
+            return Err(Status::Forbidden);
+        }
+        let now = now();
+        if relay.sent.abs_diff(now) > WINDOW {
+            return Err(Status::Unauthorized);
+        }
+        let mut seen = self.seen.write().await;
+        let ids = seen.entry(signed.peer.clone()).or_default();
+        ids.retain(|_, sent| sent.abs_diff(now) <= WINDOW);
+        if ids.insert(relay.id.clone(), relay.sent).is_some() {
+            return Err(Status::Conflict);
+        }
+        Ok(relay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(secret: &str, trust: Trust) -> Peer {
+        Peer {
+            url: "http://127.0.0.1:9".to_string(),
+            secret: secret.to_string(),
+            trust,
+        }
+    }
+
+    fn federation() -> Federation {
+        Federation::new(
+            "beta".to_string(),
+            HashMap::from([
+                ("alpha".to_string(), peer("a", Trust::Files)),
+                ("gamma".to_string(), peer("g", Trust::Text)),
+                ("delta".to_string(), peer("d", Trust::Blocked)),
+            ]),
+        )
+    }
+
+    fn relay(from: &str, data: Data) -> Relay {
+        Relay {
+            id: token(),
+            sent: now(),
+            from: from.to_string(),
+            src: 2,
+            dst: 3,
+            data,
+        }
+    }
+
+    fn signed(peer: &str, secret: &str, relay: &Relay) -> (Signed, Vec<u8>) {
+        let body = serde_json::to_vec(relay).unwrap();
+        let signed = Signed {
+            peer: peer.to_string(),
+            signature: sign(secret, &body),
+        };
+        (signed, body)
+    }
+
+    fn text() -> Data {
+        Data::Text("hi".to_string())
+    }
+
+    fn file() -> Data {
+        Data::File {
+            filename: "a.txt".to_string(),
+            file: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(parse("7@beta"), Some((7, "beta")));
+        assert_eq!(parse("7@"), None);
+        assert_eq!(parse("bob@beta"), None);
+        assert_eq!(parse("7"), None);
+    }
+
+    #[tokio::test]
+    async fn accept_signed_relay() {
+        let federation = federation();
+        let (sig, body) = signed("alpha", "a", &relay("alpha", file()));
+        let relay = federation.accept(&sig, &body).await.unwrap();
+        assert_eq!((relay.from.as_str(), relay.src, relay.dst), ("alpha", 2, 3));
+    }
+
+    #[tokio::test]
+    async fn reject_bad_signature_and_wrong_sender() {
+        let federation = federation();
+        let (sig, _) = signed("alpha", "a", &relay("alpha", text()));
+        let (_, other) = signed("alpha", "a", &relay("alpha", Data::Text("x".to_string())));
+        let err = federation.accept(&sig, &other).await.err();
+        assert_eq!(err, Some(Status::Unauthorized));
+        let (sig, body) = signed("alpha", "wrong", &relay("alpha", text()));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Unauthorized));
+        let (sig, body) = signed("alpha", "a", &relay("gamma", text()));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn enforce_trust_levels() {
+        let federation = federation();
+        let (sig, body) = signed("gamma", "g", &relay("gamma", text()));
+        assert!(federation.accept(&sig, &body).await.is_ok());
+        let (sig, body) = signed("gamma", "g", &relay("gamma", file()));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Forbidden));
+        let receipt = Data::Receipt { mid: 1, read: true };
+        let (sig, body) = signed("alpha", "a", &relay("alpha", receipt));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Forbidden));
+        let (sig, body) = signed("delta", "d", &relay("delta", text()));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Forbidden));
+        let (sig, body) = signed("omega", "o", &relay("omega", text()));
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::NotFound));
+    }
+
+    #[tokio::test]
+    async fn reject_replayed_and_stale_relays() {
+        let federation = federation();
+        let (sig, body) = signed("alpha", "a", &relay("alpha", text()));
+        assert!(federation.accept(&sig, &body).await.is_ok());
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Conflict));
+        let mut stale = relay("alpha", text());
+        stale.sent -= WINDOW + 1;
+        let (sig, body) = signed("alpha", "a", &stale);
+        let err = federation.accept(&sig, &body).await.err();
+        assert_eq!(err, Some(Status::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn aliases_are_stable() {
+        let federation = federation();
+        let mut next = 10;
+        let mut alloc = || {
+            next += 1;
+            next
+        };
+        let a = federation.alias("alpha", 2, &mut alloc).await.unwrap();
+        let b = federation.alias("alpha", 2, &mut alloc).await.unwrap();
+        let c = federation.alias("alpha", 3, &mut alloc).await.unwrap();
+        assert_eq!((a, b, c), (11, 11, 12));
+        assert_eq!(federation.remote(12).await, Some(("alpha".to_string(), 3)));
+        let err = federation.alias("delta", 2, &mut alloc).await.err();
+        assert_eq!(err, Some(Status::Forbidden));
+    }
+}
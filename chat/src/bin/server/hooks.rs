@@ -0,0 +1,229 @@
+use crate::Server;
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use chat::Message;
+use hmac::{Hmac, Mac};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest},
+    Request,
+};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Arc};
+use tokio::time::{sleep, Duration};
+
+const ATTEMPTS: u32 = 5;
+const RETRY_BASE: Duration = Duration::from_millis(500);
+
+pub struct Outgoing {
+    pub id: u64,
+    pub target: u32,
+    pub url: String,
+    pub secret: String,
+}
+
+pub struct Incoming {
+    pub room: u32,
+    pub as_id: u32,
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    hook: u64,
+    dst: u32,
+    message: &'a Message,
+}
+
+pub fn token() -> String {
+    let mut buf = [0; 16];
+    OsRng.fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("can key hmac");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    format!(
+        "sha256={}",
+        digest
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    )
+}
+
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(hex) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let Some(digest) = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("can key hmac");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+async fn deliver(client: reqwest::Client, id: u64, url: String, signature: String, body: Vec<u8>) {
+    for attempt in 0..ATTEMPTS {
+        let result = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-Chat-Hook", id)
+            .header("X-Chat-Signature", &signature)
+            .header("X-Chat-Attempt", attempt + 1)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        match result {
+            Ok(_) => return,
+            Err(e) if attempt + 1 == ATTEMPTS => println!("hook {id}: giving up on {url}: {e}"),
+            Err(_) => sleep(RETRY_BASE * (1 << attempt)).await,
+        }
+    }
+}
+
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(server) = req.rocket().state::<Arc<Server>>() else {
+            return request::Outcome::Failure((Status::InternalServerError, ()));
+        };
+        let hooks = server.hooks.read().await;
+        let Some(admin) = &hooks.admin else {
+            return request::Outcome::Failure((Status::NotFound, ()));
+        };
+        let given = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        let same = given.len() == admin.len()
+            && given
+                .bytes()
+                .zip(admin.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+        if same {
+            request::Outcome::Success(Admin)
+        } else {
+            request::Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+pub struct Hooks {
+    admin: Option<String>,
+    last: u64,
+    outgoing: Vec<Outgoing>,
+    incoming: HashMap<String, Incoming>,
+    client: reqwest::Client,
+}
+impl Hooks {
+    pub fn new() -> Self {
+        Self {
+            admin: std::env::var("CHAT_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            last: 0,
+            outgoing: vec![],
+            incoming: HashMap::new(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("can build client"),
+        }
+    }
+    pub fn add_outgoing(&mut self, target: u32, url: String, secret: Option<String>) -> &Outgoing {
+        self.last += 1;
+        self.outgoing.push(Outgoing {
+            id: self.last,
+            target,
+            url,
+            secret: secret.unwrap_or_else(token),
+        });
+        self.outgoing.last().unwrap()
+    }
+    pub fn remove_outgoing(&mut self, id: u64) -> bool {
+        let len = self.outgoing.len();
+        self.outgoing.retain(|hook| hook.id != id);
+        self.outgoing.len() != len
+    }
+    pub fn add_incoming(&mut self, room: u32, as_id: u32) -> String {
+        let token = token();
+        self.incoming
+            .insert(token.clone(), Incoming { room, as_id });
+        token
+    }
+    pub fn remove_incoming(&mut self, token: &str) -> bool {
+        self.incoming.remove(token).is_some()
+    }
+    pub fn incoming(&self, token: &str) -> Option<&Incoming> {
+        self.incoming.get(token)
+    }
+    pub fn owns(&self, id: u32) -> bool {
+        self.outgoing.iter().any(|hook| hook.target == id)
+            || self.incoming.values().any(|hook| hook.as_id == id)
+    }
+    pub fn fire(&self, dst: u32, message: &Message) {
+        for hook in self.outgoing.iter().filter(|hook| hook.target == dst) {
+            let body = serde_json::to_vec(&Payload {
+                hook: hook.id,
+                dst,
+                message,
+            })
+            .expect("can serialize");
+            tokio::spawn(deliver(
+                self.client.clone(),
+                hook.id,
+                hook.url.clone(),
+                sign(&hook.secret, &body),
+                body,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_accepts_own_signature() {
+        let body = br#"{"hook":1,"dst":1}"#;
+        let signature = sign("secret", body);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert!(verify("secret", body, &signature));
+    }
+
+    #[test]
+    fn verify_rejects_tampering() {
+        let body = br#"{"hook":1,"dst":1}"#;
+        let signature = sign("secret", body);
+        assert!(!verify("secret", br#"{"hook":1,"dst":2}"#, &signature));
+        assert!(!verify("other", body, &signature));
+        assert!(!verify("secret", body, &signature[7..]));
+        assert!(!verify("secret", body, "sha256=zz"));
+        assert!(!verify("secret", body, &signature[..signature.len() - 1]));
+    }
+
+    #[test]
+    fn owns_outgoing_targets_and_incoming_ids() {
+        let mut hooks = Hooks::new();
+        hooks.add_outgoing(50, "http://127.0.0.1:9".to_string(), None);
+        let token = hooks.add_incoming(1, 60);
+        assert!(hooks.owns(50) && hooks.owns(60) && !hooks.owns(1));
+        assert!(hooks.remove_incoming(&token));
+        assert!(!hooks.owns(60));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::{socket::Lines, Server};
+use chat::{Capability, Data, Hello, Message};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+    time::{interval, Duration},
+};
+
+const HOST: &str = "chat";
+const TICK: Duration = Duration::from_secs(1);
+const MAX_LINE: usize = 8 << 10;
+
+struct Gateway {
+    server: Arc<Server>,
+    nicks: RwLock<HashMap<String, Option<u32>>>,
+}
+impl Gateway {
+    async fn nick(&self, id: u32) -> String {
+        self.nicks
+            .read()
+            .await
+            .iter()
+            .find(|(_, v)| **v == Some(id))
+            .map_or_else(|| format!("u{id}"), |(nick, _)| nick.clone())
+    }
+    async fn resolve(&self, target: &str) -> Option<u32> {
+        if target.starts_with('#') {
+            let name = target.trim_start_matches('#');
+            return self.server.rooms.read().await.by_name.get(name).copied();
+        }
+        if let Some(id) = self.nicks.read().await.get(target) {
+            return *id;
+        }
+        target.trim_start_matches('u').parse().ok()
+    }
+    async fn channel(&self, room: u32) -> Option<String> {
+        self.server
+            .rooms
+            .read()
+            .await
+            .by_name
+            .iter()
+            .find(|(_, id)| **id == room)
+            .map(|(name, _)| format!("#{name}"))
+    }
+}
+
+struct Client {
+    nick: Option<String>,
+    user: bool,
+    id: Option<u32>,
+    channels: HashSet<u32>,
+}
+impl Client {
+    fn new() -> Self {
+        Self {
+            nick: None,
+            user: false,
+            id: None,
+            channels: HashSet::new(),
+        }
+    }
+}
+
+pub fn listen(server: Arc<Server>) {
+    let Ok(addr) = env::var("CHAT_IRC") else {
+        return;
+    };
+    let gateway = Arc::new(Gateway {
+        server,
+        nicks: RwLock::new(HashMap::new()),
+    });
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&addr)
+            .await
+            .expect("can bind irc listener");
+        println!("listening on irc {addr}");
+        loop {
+            if let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(gateway.clone(), stream));
+            }
+        }
+    });
+}
+
+fn parse(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1;
+    }
+    let (head, trailing) = match rest.split_once(" :") {
+        Some((head, trailing)) => (head, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = head.split_whitespace();
+    let command = words.next()?.to_ascii_uppercase();
+    let mut params = words.map(str::to_string).collect::<Vec<_>>();
+    params.extend(trailing.map(str::to_string));
+    Some((command, params))
+}
+
+async fn serve(gateway: Arc<Gateway>, stream: TcpStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = Lines::new(reader, MAX_LINE);
+    let mut client = Client::new();
+    let mut tick = interval(TICK);
+    loop {
+        let wake = gateway.server.wake.notified();
+        tokio::pin!(wake);
+        wake.as_mut().enable();
+        let mut out = vec![];
+        if let Some(id) = client.id {
+            for msg in gateway.server.drain(id).await {
+                out.extend(deliver(&gateway, &client, msg).await);
+            }
+        }
+        if write(&mut writer, &out).await.is_err() {
+            break;
+        }
+        tokio::select! {
+            _ = wake => {}
+            _ = tick.tick() => {}
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    let Some((command, params)) = parse(&line) else {
+                        continue;
+                    };
+                    let (out, quit) = handle(&gateway, &mut client, &command, &params).await;
+                    if write(&mut writer, &out).await.is_err() || quit {
+                        break;
+                    }
+                }
+                _ => break,
+            },
+        }
+    }
+    if let Some(nick) = client.nick {
+        gateway.nicks.write().await.remove(&nick);
+    }
+}
+
+async fn write(writer: &mut (impl AsyncWrite + Unpin), lines: &[String]) -> std::io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let mut buf = String::new();
+    for line in lines {
+        buf.push_str(line);
+        buf.push_str("\r\n");
+    }
+    writer.write_all(buf.as_bytes()).await?;
+    writer.flush().await
+}
+
+async fn deliver(gateway: &Gateway, client: &Client, msg: Message) -> Vec<String> {
+    let Some(nick) = &client.nick else {
+        return vec![];
+    };
+    let text = match msg.data {
+        Data::Text(text) => text,
+        Data::File { filename, file } => {
+            format!(
+                "\x01ACTION sent a file: {filename} ({} bytes)\x01",
+                file.len()
+            )
+        }
+        _ => return vec![],
+    };
+    let (from, target) = match gateway.channel(msg.id).await {
+        Some(_) if !client.channels.contains(&msg.id) => return vec![],
+        Some(channel) => {
+            let sender = gateway.server.senders.read().await.get(&msg.mid).copied();
+            (sender.unwrap_or(msg.id), channel)
+        }
+        None => (msg.id, nick.clone()),
+    };
+    let from = gateway.nick(from).await;
+    text.split(['\r', '\n'])
+        .filter(|line| !line.is_empty())
+        .map(|line| format!(":{from}!{from}@{HOST} PRIVMSG {target} :{line}"))
+        .collect()
+}
+
+fn reply(nick: &str, code: &str, text: &str) -> String {
+    format!(":{HOST} {code} {nick} {text}")
+}
+
+async fn handle(
+    gateway: &Gateway,
+    client: &mut Client,
+    command: &str,
+    params: &[String],
+) -> (Vec<String>, bool) {
+    let me = client.nick.clone().unwrap_or_else(|| "*".to_string());
+    let mut out = vec![];
+    match command {
+        "NICK" => {
+            let Some(nick) = params.first().filter(|nick| !nick.starts_with('#')) else {
+                out.push(reply(&me, "431", ":No nickname given"));
+                return (out, false);
+            };
+            let mut nicks = gateway.nicks.write().await;
+            if nicks.contains_key(nick) {
+                out.push(reply(
+                    &me,
+                    "433",
+                    &format!("{nick} :Nickname is already in use"),
+                ));
+                return (out, false);
+            }
+            if let Some(old) = client.nick.replace(nick.clone()) {
+                nicks.remove(&old);
+                out.push(format!(":{old}!{old}@{HOST} NICK {nick}"));
+            }
+            nicks.insert(nick.clone(), client.id);
+        }
+        "USER" => client.user = true,
+        "PING" => {
+            let token = params.first().map_or(HOST, |s| s.as_str());
+            out.push(format!(":{HOST} PONG {HOST} :{token}"));
+            return (out, false);
+        }
+        "QUIT" => {
+            out.push(format!("ERROR :Closing link ({me})"));
+            return (out, true);
+        }
+        "CAP" | "PASS" | "PONG" => return (out, false),
+        _ if client.id.is_none() => {
+            out.push(reply(&me, "451", ":You have not registered"));
+            return (out, false);
+        }
+        "JOIN" => {
+            let id = client.id.expect("can read registered id");
+            for channel in params.first().into_iter().flat_map(|s| s.split(',')) {
+                let room = gateway.server.join(id, channel).await;
+                client.channels.insert(room);
+                let channel = gateway
+                    .channel(room)
+                    .await
+                    .unwrap_or_else(|| channel.to_string());
+                out.push(format!(":{me}!{me}@{HOST} JOIN {channel}"));
+                out.push(reply(&me, "331", &format!("{channel} :No topic is set")));
+                let members = gateway
+                    .server
+                    .rooms
+                    .read()
+                    .await
+                    .members
+                    .get(&room)
+                    .cloned()
+                    .unwrap_or_default();
+                let mut names = vec![];
+                for member in members {
+                    names.push(gateway.nick(member).await);
+                }
+                if !names.contains(&me) {
+                    names.push(me.clone());
+                }
+                out.push(reply(
+                    &me,
+                    "353",
+                    &format!("= {channel} :{}", names.join(" ")),
+                ));
+                out.push(reply(&me, "366", &format!("{channel} :End of /NAMES list")));
+            }
+        }
+        "PART" => {
+            let id = client.id.expect("can read registered id");
+            for channel in params.first().into_iter().flat_map(|s| s.split(',')) {
+                if let Some(room) = gateway.resolve(channel).await {
+                    gateway.server.leave(id, room).await;
+                    client.channels.remove(&room);
+                }
+                out.push(format!(":{me}!{me}@{HOST} PART {channel}"));
+            }
+        }
+        "PRIVMSG" | "NOTICE" => {
+            let id = client.id.expect("can read registered id");
+            let (Some(target), Some(text)) = (params.first(), params.get(1)) else {
+                out.push(reply(&me, "412", ":No text to send"));
+                return (out, false);
+            };
+            let Some(dst) = gateway.resolve(target).await else {
+                out.push(reply(
+                    &me,
+                    "401",
+                    &format!("{target} :No such nick/channel"),
+                ));
+                return (out, false);
+            };
+            let text = match text.strip_prefix("\x01ACTION ") {
+                Some(action) => format!("*{me} {}*", action.trim_end_matches('\x01')),
+                None => text.clone(),
+            };
+            if let Err(status) = gateway
+                .server
+                .route(dst, Message::new(id, Data::Text(text)))
+                .await
+            {
+                let reason = status.reason_lossy();
+                out.push(reply(
+                    &me,
+                    "404",
+                    &format!("{target} :Cannot send ({reason})"),
+                ));
+            }
+        }
+        _ => out.push(reply(&me, "421", &format!("{command} :Unknown command"))),
+    }
+    if client.id.is_none() && client.user {
+        if let Some(nick) = client.nick.clone() {
+            let mut nicks = gateway.nicks.write().await;
+            if nicks.get(&nick) != Some(&None) {
+                client.nick = None;
+                out.push(reply(
+                    "*",
+                    "433",
+                    &format!("{nick} :Nickname is already in use"),
+                ));
+                return (out, false);
+            }
+            let hello = gateway
+                .server
+                .login(&Hello::new(&[Capability::Files, Capability::Rooms]))
+                .await;
+            let id = hello.id.expect("can read session id");
+            client.id = Some(id);
+            nicks.insert(nick.clone(), Some(id));
+            out.push(reply(
+                &nick,
+                "001",
+                &format!(":Welcome to chat, {nick} (id {id})"),
+            ));
+            out.push(reply(&nick, "002", &format!(":Your host is {HOST}")));
+            out.push(reply(&nick, "003", ":This server bridges IRC to chat"));
+            out.push(reply(
+                &nick,
+                "004",
+                &format!("{HOST} chat-{} o o", env!("CARGO_PKG_VERSION")),
+            ));
+            out.push(reply(&nick, "422", ":MOTD File is missing"));
+        }
+    }
+    (out, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway() -> Gateway {
+        Gateway {
+            server: Arc::new(Server::new()),
+            nicks: RwLock::new(HashMap::new()),
+        }
+    }
+
+    async fn send(gateway: &Gateway, client: &mut Client, line: &str) -> Vec<String> {
+        let (command, params) = parse(line).unwrap();
+        handle(gateway, client, &command, &params).await.0
+    }
+
+    async fn register(gateway: &Gateway, nick: &str) -> Client {
+        let mut client = Client::new();
+        send(gateway, &mut client, &format!("NICK {nick}")).await;
+        let out = send(gateway, &mut client, "USER u 0 * :U").await;
+        assert!(out[0].contains(" 001 "), "{out:?}");
+        client
+    }
+
+    fn codes(out: &[String]) -> Vec<&str> {
+        out.iter()
+            .filter_map(|line| line.split(' ').nth(1))
+            .collect()
+    }
+
+    #[test]
+    fn parse_prefix_params_and_trailing() {
+        assert_eq!(
+            parse(":n!u@h privmsg #a :hi there\r\n"),
+            Some((
+                "PRIVMSG".to_string(),
+                vec!["#a".to_string(), "hi there".to_string()]
+            ))
+        );
+        assert_eq!(
+            parse("USER u 0 *  :Real Name"),
+            Some((
+                "USER".to_string(),
+                vec!["u", "0", "*", "Real Name"]
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect()
+            ))
+        );
+        assert_eq!(parse("PING"), Some(("PING".to_string(), vec![])));
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(":prefix-only"), None);
+    }
+
+    #[tokio::test]
+    async fn nicks_are_reserved_and_renamed() {
+        let gateway = gateway();
+        let mut ann = register(&gateway, "ann").await;
+        let mut other = Client::new();
+        let out = send(&gateway, &mut other, "NICK ann").await;
+        assert_eq!(codes(&out), ["433"]);
+        send(&gateway, &mut other, "NICK bob").await;
+        let out = send(&gateway, &mut other, "NICK cat").await;
+        assert_eq!(out, [":bob!bob@chat NICK cat"]);
+        let out = send(&gateway, &mut other, "NICK ann").await;
+        assert_eq!(codes(&out), ["433"]);
+        let out = send(&gateway, &mut ann, "NICK ada").await;
+        assert_eq!(out, [":ann!ann@chat NICK ada"]);
+        let id = ann.id.unwrap();
+        assert_eq!(gateway.nick(id).await, "ada");
+        assert_eq!(gateway.resolve("ada").await, Some(id));
+        assert_eq!(gateway.resolve("ann").await, None);
+        assert_eq!(gateway.resolve("cat").await, None);
+        assert_eq!(codes(&send(&gateway, &mut other, "NICK #x").await), ["431"]);
+    }
+
+    #[tokio::test]
+    async fn commands_need_registration() {
+        let gateway = gateway();
+        let mut client = Client::new();
+        let out = send(&gateway, &mut client, "JOIN #a").await;
+        assert_eq!(codes(&out), ["451"]);
+        let out = send(&gateway, &mut client, "PING :tok").await;
+        assert_eq!(out, [":chat PONG chat :tok"]);
+        let (_, quit) = handle(&gateway, &mut client, "QUIT", &[]).await;
+        assert!(quit);
+    }
+
+    #[tokio::test]
+    async fn deliver_splits_on_cr_and_lf() {
+        let gateway = gateway();
+        let ann = register(&gateway, "ann").await;
+        let msg = Message::new(7, Data::Text("hi\rQUIT :x\r\nPRIVMSG #a :y".to_string()));
+        let out = deliver(&gateway, &ann, msg).await;
+        assert_eq!(
+            out,
+            [
+                ":u7!u7@chat PRIVMSG ann :hi",
+                ":u7!u7@chat PRIVMSG ann :QUIT :x",
+                ":u7!u7@chat PRIVMSG ann :PRIVMSG #a :y",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_messages_need_membership() {
+        let gateway = gateway();
+        let mut ann = register(&gateway, "ann").await;
+        let general = || Message::new(1, Data::Text("hello".to_string()));
+        assert!(deliver(&gateway, &ann, general()).await.is_empty());
+        send(&gateway, &mut ann, "JOIN #general").await;
+        let out = deliver(&gateway, &ann, general()).await;
+        assert_eq!(out, [":u1!u1@chat PRIVMSG #general :hello"]);
+        send(&gateway, &mut ann, "PART #general").await;
+        assert!(deliver(&gateway, &ann, general()).await.is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+mod federation;
+mod filter;
+mod hooks;
+mod irc;
+mod socket;
+mod wire;
+
+use chat::{Capability, Data, Hello, Message};
+use federation::{Federation, Signed};
+use filter::{Chain, Passed};
+use hooks::{Admin, Hooks};
+use rocket::data::ToByteUnit;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::{delete, get, post, routes};
+use rocket::{fairing::AdHoc, launch, State};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Notify, RwLock};
+use wire::{Metrics, Wire};
+
+struct TmpMessage {
+    group_chat: Vec<Arc<Message>>,
+    chat: Vec<Message>,
+}
+impl TmpMessage {
+    pub fn new() -> Self {
+        Self {
+            group_chat: vec![],
+            chat: vec![],
+        }
+    }
+    pub fn push(&mut self, msg: Message) {
+        self.chat.push(msg);
+    }
+    pub fn push_group(&mut self, msg: Arc<Message>) {
+        self.group_chat.push(msg);
+    }
+    pub fn scrub(&mut self, mid: u64, data: &Data) {
+        let edit = |msg: &Message| match data {
+            Data::Edit { text, .. } => Some(Message {
+                data: Data::Text(text.clone()),
+                ..msg.clone()
+            }),
+            _ => None,
+        };
+        self.chat = self
+            .chat
+            .drain(..)
+            .filter_map(|msg| {
+                if msg.mid == mid {
+                    edit(&msg)
+                } else {
+                    Some(msg)
+                }
+            })
+            .collect();
+        self.group_chat = self
+            .group_chat
+            .drain(..)
+            .filter_map(|msg| {
+                if msg.mid == mid {
+                    edit(&msg).map(Arc::new)
+                } else {
+                    Some(msg)
+                }
+            })
+            .collect();
+    }
+    pub fn extract(&mut self) -> Vec<Message> {
+        let mut msgs = self.chat.drain(..).collect::<Vec<_>>();
+        msgs.extend(self.group_chat.drain(..).map(|v| v.as_ref().clone()));
+        msgs
+    }
+}
+struct Rooms {
+    by_name: HashMap<String, u32>,
+    members: HashMap<u32, HashSet<u32>>,
+}
+impl Rooms {
+    pub fn new() -> Self {
+        Self {
+            by_name: HashMap::from([("general".to_string(), 1)]),
+            members: HashMap::new(),
+        }
+    }
+}
+struct Server {
+    last_id: AtomicU32,
+    // msg: std::collections::HashMap<u32, Massage>,
+    msg: RwLock<std::collections::HashMap<u32, TmpMessage>>,
+    rooms: RwLock<Rooms>,
+    last_mid: AtomicU64,
+    senders: RwLock<HashMap<u64, u32>>,
+    seen: RwLock<HashMap<u32, Instant>>,
+    hooks: RwLock<Hooks>,
+    filters: Chain,
+    sessions: RwLock<HashMap<u32, Hello>>,
+    wake: Notify,
+    federation: Federation,
+}
+impl Server {
+    fn new() -> Self {
+        Self {
+            last_id: AtomicU32::new(2),
+            msg: RwLock::new(std::collections::HashMap::new()),
+            rooms: RwLock::new(Rooms::new()),
+            last_mid: AtomicU64::new(1),
+            senders: RwLock::new(HashMap::new()),
+            seen: RwLock::new(HashMap::new()),
+            hooks: RwLock::new(Hooks::new()),
+            filters: Chain::from_env(),
+            sessions: RwLock::new(HashMap::new()),
+            wake: Notify::new(),
+            federation: Federation::from_env(),
+        }
+    }
+    async fn login(&self, hello: &Hello) -> Hello {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed);
+        let mut agreed = Hello::new(Capability::ALL).negotiate(hello);
+        agreed.id = Some(id);
+        self.sessions.write().await.insert(id, agreed.clone());
+        agreed
+    }
+    async fn join(&self, id: u32, room: &str) -> u32 {
+        let mut rooms = self.rooms.write().await;
+        let room = *rooms
+            .by_name
+            .entry(room.trim_start_matches('#').to_string())
+            .or_insert_with(|| self.last_id.fetch_add(1, Ordering::Relaxed));
+        if room != 1 {
+            rooms.members.entry(room).or_default().insert(id);
+        }
+        room
+    }
+    async fn leave(&self, id: u32, room: u32) {
+        if let Some(members) = self.rooms.write().await.members.get_mut(&room) {
+            members.remove(&id);
+        }
+    }
+    async fn drain(&self, dst: u32) -> Vec<Message> {
+        self.seen.write().await.insert(dst, Instant::now());
+        let msgs = self
+            .msg
+            .write()
+            .await
+            .entry(dst)
+            .or_insert(TmpMessage::new())
+            .extract();
+        let msgs = match self.sessions.read().await.get(&dst) {
+            Some(hello) => msgs
+                .into_iter()
+                .filter(|msg| msg.data.capability().is_none_or(|c| hello.supports(c)))
+                .map(|msg| Message {
+                    reply: msg.reply.filter(|_| hello.supports(Capability::Replies)),
+                    ..msg
+                })
+                .collect(),
+            None => msgs,
+        };
+        let delivered = msgs
+            .iter()
+            .filter(|msg| msg.mid != 0 && !msg.data.is_event())
+            .map(|msg| msg.mid)
+            .collect::<Vec<_>>();
+        self.receipt(dst, delivered, false).await;
+        msgs
+    }
+    async fn receipt(&self, reader: u32, mids: impl IntoIterator<Item = u64>, read: bool) {
+        let senders = self.senders.read().await;
+        let mut boxes = self.msg.write().await;
+        let hooks = self.hooks.read().await;
+        let mut pushed = false;
+        for mid in mids {
+            if let Some(src) = senders.get(&mid).filter(|src| **src != reader) {
+                if hooks.owns(*src) || self.federation.remote(*src).await.is_some() {
+                    continue;
+                }
+                boxes
+                    .entry(*src)
+                    .or_insert(TmpMessage::new())
+                    .push(Message::new(reader, Data::Receipt { mid, read }));
+                pushed = true;
+            }
+        }
+        if pushed {
+            self.wake.notify_waiters();
+        }
+    }
+    async fn route(&self, dst: u32, mut msg: Message) -> Result<u64, Status> {
+        if let Data::Edit { mid, .. } | Data::Delete { mid } = msg.data {
+            if self.senders.read().await.get(&mid) != Some(&msg.id) {
+                return Err(Status::Forbidden);
+            }
+        }
+        if let Data::Reaction { mid, from, .. } = &mut msg.data {
+            if !self.senders.read().await.contains_key(mid) {
+                return Err(Status::NotFound);
+            }
+            *from = msg.id;
+        }
+        let Passed {
+            dst,
+            mut msg,
+            replies,
+        } = self.filters.run(dst, msg).map_err(|reason| {
+            println!("rejected: {reason}");
+            Status::UnprocessableEntity
+        })?;
+        if let Some((server, user)) = self.federation.remote(dst).await {
+            self.federation.relay(&server, user, &msg).await?;
+            let mid = self.last_mid.fetch_add(1, Ordering::Relaxed);
+            self.senders.write().await.insert(mid, msg.id);
+            return Ok(mid);
+        }
+        if let Data::Edit { mid, .. } | Data::Delete { mid } = msg.data {
+            for tm in self.msg.write().await.values_mut() {
+                tm.scrub(mid, &msg.data);
+            }
+        }
+        let src = msg.id;
+        let mid = self.last_mid.fetch_add(1, Ordering::Relaxed);
+        self.senders.write().await.insert(mid, msg.id);
+        msg.mid = mid;
+        self.hooks.read().await.fire(dst, &msg);
+        let members = self.rooms.read().await.members.get(&dst).cloned();
+        if dst == 1 {
+            let msg = Arc::new(Message { id: 1, ..msg });
+            self.msg.write().await.iter_mut().for_each(|(id, tm)| {
+                if *id != src {
+                    tm.push_group(msg.clone());
+                }
+            });
+        } else if let Some(members) = members {
+            let msg = Arc::new(Message { id: dst, ..msg });
+            let mut boxes = self.msg.write().await;
+            for id in members.into_iter().filter(|id| *id != src) {
+                boxes
+                    .entry(id)
+                    .or_insert(TmpMessage::new())
+                    .push_group(msg.clone());
+            }
+        } else {
+            println!("{}: {}", dst, msg);
+            if !self.hooks.read().await.owns(dst) {
+                self.msg
+                    .write()
+                    .await
+                    .entry(dst)
+                    .or_insert(TmpMessage::new())
+                    .push(msg);
+            }
+        }
+        for text in replies {
+            let mut reply = Message::new(dst, Data::Text(text)).with_reply(Some(mid));
+            reply.mid = self.last_mid.fetch_add(1, Ordering::Relaxed);
+            self.senders.write().await.insert(reply.mid, dst);
+            self.msg
+                .write()
+                .await
+                .entry(src)
+                .or_insert(TmpMessage::new())
+                .push(reply);
+        }
+        self.wake.notify_waiters();
+        Ok(mid)
+    }
+}
+
+#[get("/")]
+async fn index(state: &State<Arc<Server>>) -> String {
+    let count = state.last_id.fetch_add(1, Ordering::Relaxed);
+    state
+        .sessions
+        .write()
+        .await
+        .insert(count, Hello::legacy(count));
+    count.to_string()
+}
+
+#[post("/hello", format = "json", data = "<hello>")]
+async fn hello(hello: Json<Hello>, state: &State<Arc<Server>>) -> Json<Hello> {
+    Json::from(state.login(&hello).await)
+}
+
+#[post("/join?<id>&<room>")]
+async fn join(id: u32, room: &str, state: &State<Arc<Server>>) -> String {
+    state.join(id, room).await.to_string()
+}
+
+#[post("/leave?<id>&<room>")]
+async fn leave(id: u32, room: u32, state: &State<Arc<Server>>) {
+    state.leave(id, room).await;
+}
+
+#[post("/send?<dst>", data = "<msg>")]
+async fn send(dst: u32, msg: Wire<Message>, state: &State<Arc<Server>>) -> Result<String, Status> {
+    state.route(dst, msg.0).await.map(|mid| mid.to_string())
+}
+
+#[post("/read?<id>", format = "json", data = "<mids>")]
+async fn read(id: u32, mids: Json<Vec<u64>>, state: &State<Arc<Server>>) {
+    state.receipt(id, mids.into_inner(), true).await;
+}
+
+#[get("/recv?<dst>")]
+async fn recv(dst: u32, state: &State<Arc<Server>>) -> Result<Wire<Vec<Message>>, Status> {
+    if !state.sessions.read().await.contains_key(&dst) {
+        return Err(Status::NotFound);
+    }
+    Ok(Wire(state.drain(dst).await))
+}
+
+#[post("/hooks/outgoing?<target>&<url>&<secret>")]
+async fn add_outgoing(
+    _admin: Admin,
+    target: Option<u32>,
+    url: String,
+    secret: Option<String>,
+    state: &State<Arc<Server>>,
+) -> Result<Value, Status> {
+    let mut hooks = state.hooks.write().await;
+    let target = match target {
+        Some(target) => {
+            let room = state
+                .rooms
+                .read()
+                .await
+                .by_name
+                .values()
+                .any(|r| *r == target);
+            if !room && !hooks.owns(target) {
+                return Err(Status::Conflict);
+            }
+            target
+        }
+        None => state.last_id.fetch_add(1, Ordering::Relaxed),
+    };
+    let hook = hooks.add_outgoing(target, url, secret);
+    Ok(json!({ "id": hook.id, "target": target, "secret": hook.secret }))
+}
+
+#[delete("/hooks/outgoing/<id>")]
+async fn remove_outgoing(_admin: Admin, id: u64, state: &State<Arc<Server>>) -> Status {
+    if state.hooks.write().await.remove_outgoing(id) {
+        Status::NoContent
+    } else {
+        Status::NotFound
+    }
+}
+
+#[post("/hooks/incoming?<room>")]
+async fn add_incoming(_admin: Admin, room: u32, state: &State<Arc<Server>>) -> Value {
+    let as_id = state.last_id.fetch_add(1, Ordering::Relaxed);
+    let token = state.hooks.write().await.add_incoming(room, as_id);
+    json!({ "id": as_id, "url": format!("/hooks/in/{token}"), "token": token })
+}
+
+#[delete("/hooks/incoming/<token>")]
+async fn remove_incoming(_admin: Admin, token: &str, state: &State<Arc<Server>>) -> Status {
+    if state.hooks.write().await.remove_incoming(token) {
+        Status::NoContent
+    } else {
+        Status::NotFound
+    }
+}
+
+#[post("/hooks/in/<token>", data = "<body>")]
+async fn incoming(token: &str, body: String, state: &State<Arc<Server>>) -> Result<String, Status> {
+    let (room, as_id) = match state.hooks.read().await.incoming(token) {
+        Some(hook) => (hook.room, hook.as_id),
+        None => return Err(Status::NotFound),
+    };
+    let text = match serde_json::from_str::<Value>(&body) {
+        Ok(value) => value["text"]
+            .as_str()
+            .ok_or(Status::UnprocessableEntity)?
+            .to_string(),
+        Err(_) => body,
+    };
+    state
+        .route(room, Message::new(as_id, Data::Text(text)))
+        .await
+        .map(|mid| mid.to_string())
+}
+
+#[get("/federation/resolve?<addr>")]
+async fn resolve(addr: &str, state: &State<Arc<Server>>) -> Result<String, Status> {
+    let (user, server) = federation::parse(addr).ok_or(Status::BadRequest)?;
+    if server == state.federation.name {
+        return Ok(user.to_string());
+    }
+    state
+        .federation
+        .alias(server, user, || {
+            state.last_id.fetch_add(1, Ordering::Relaxed)
+        })
+        .await
+        .map(|id| id.to_string())
+}
+
+#[post("/federation/relay", data = "<body>")]
+async fn relay(
+    signed: Signed,
+    body: rocket::Data<'_>,
+    state: &State<Arc<Server>>,
+) -> Result<String, Status> {
+    let body = body
+        .open(16.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|_| Status::BadRequest)?;
+    if !body.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+    let relay = state.federation.accept(&signed, &body).await?;
+    let id = state
+        .federation
+        .alias(&relay.from, relay.src, || {
+            state.last_id.fetch_add(1, Ordering::Relaxed)
+        })
+        .await?;
+    if state.federation.remote(relay.dst).await.is_some() {
+        return Err(Status::Forbidden);
+    }
+    state
+        .route(relay.dst, Message::new(id, relay.data))
+        .await
+        .map(|mid| mid.to_string())
+}
+
+#[get("/metrics")]
+fn metrics(metrics: &State<Metrics>) -> String {
+    metrics.render()
+}
+
+#[get("/presence?<id>")]
+async fn presence(id: Vec<u32>, state: &State<Arc<Server>>) -> Json<HashMap<u32, u64>> {
+    let seen = state.seen.read().await;
+    Json::from(
+        id.into_iter()
+            .filter_map(|id| Some((id, seen.get(&id)?.elapsed().as_millis() as u64)))
+            .collect::<HashMap<_, _>>(),
+    )
+}
+
+#[launch]
+fn rocket() -> _ {
+    rocket::build()
+        .configure(rocket::config::Config::figment().merge(("log_level", "off")))
+        .mount(
+            "/",
+            routes![
+                index,
+                hello,
+                join,
+                leave,
+                send,
+                read,
+                recv,
+                presence,
+                metrics,
+                add_outgoing,
+                remove_outgoing,
+                add_incoming,
+                remove_incoming,
+                incoming,
+                resolve,
+                relay
+            ],
+        )
+        .manage(Arc::new(Server::new()))
+        .manage(Metrics::default())
+        .attach(AdHoc::on_liftoff("listeners", |rocket| {
+            Box::pin(async move {
+                if let Some(server) = rocket.state::<Arc<Server>>() {
+                    socket::listen(server.clone());
+                    irc::listen(server.clone());
+                }
+            })
+        }))
+}
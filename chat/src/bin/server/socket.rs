@@ -0,0 +1,275 @@
+use crate::Server;
+use chat::{Capability, Data, Encoding, Hello, Message};
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::{env, io, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    time::{interval, Duration},
+};
+
+const TICK: Duration = Duration::from_secs(1);
+const MAX_LINE: usize = 16 << 20;
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Hello(Hello),
+    Send {
+        dst: u32,
+        data: Data,
+        #[serde(default)]
+        reply: Option<u64>,
+    },
+    Join {
+        room: String,
+    },
+    Leave {
+        room: u32,
+    },
+    Read {
+        mids: Vec<u64>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Reply {
+    Hello(Hello),
+    Sent { mid: u64 },
+    Joined { room: u32 },
+    Ok,
+    Message(Message),
+    Error { status: u16, reason: String },
+}
+impl From<Status> for Reply {
+    fn from(status: Status) -> Self {
+        Reply::Error {
+            status: status.code,
+            reason: status.reason_lossy().to_string(),
+        }
+    }
+}
+
+pub struct Lines<R> {
+    reader: BufReader<R>,
+    buf: Vec<u8>,
+    max: usize,
+}
+impl<R: AsyncRead + Unpin> Lines<R> {
+    pub fn new(reader: R, max: usize) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            buf: vec![],
+            max,
+        }
+    }
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        let limit = (self.max + 1).saturating_sub(self.buf.len()) as u64;
+        (&mut self.reader)
+            .take(limit)
+            .read_until(b'\n', &mut self.buf)
+            .await?;
+        if self.buf.ends_with(b"\n") {
+            self.buf.pop();
+            if self.buf.ends_with(b"\r") {
+                self.buf.pop();
+            }
+        } else if self.buf.len() > self.max {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        } else if self.buf.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(std::mem::take(&mut self.buf))
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+pub fn listen(server: Arc<Server>) {
+    if let Ok(addr) = env::var("CHAT_TCP") {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(&addr)
+                .await
+                .expect("can bind tcp listener");
+            println!("listening on tcp {addr}");
+            loop {
+                if let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(server.clone(), stream));
+                }
+            }
+        });
+    }
+    #[cfg(unix)]
+    if let Ok(path) = env::var("CHAT_SOCKET") {
+        use std::os::unix::fs::FileTypeExt;
+        if let Ok(meta) = std::fs::symlink_metadata(&path) {
+            assert!(
+                meta.file_type().is_socket(),
+                "{path} exists and is not a socket"
+            );
+            std::fs::remove_file(&path).expect("can remove stale socket");
+        }
+        let listener = tokio::net::UnixListener::bind(&path).expect("can bind unix socket");
+        println!("listening on unix {path}");
+        tokio::spawn(async move {
+            loop {
+                if let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(server.clone(), stream));
+                }
+            }
+        });
+    }
+}
+
+async fn serve<S: AsyncRead + AsyncWrite>(server: Arc<Server>, stream: S) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = Lines::new(reader, MAX_LINE);
+    let mut session = None;
+    let mut tick = interval(TICK);
+    loop {
+        let wake = server.wake.notified();
+        tokio::pin!(wake);
+        wake.as_mut().enable();
+        let mut replies = vec![];
+        if let Some(id) = session.as_ref().and_then(|hello: &Hello| hello.id) {
+            replies.extend(server.drain(id).await.into_iter().map(Reply::Message));
+        }
+        for reply in replies {
+            if write(&mut writer, &reply).await.is_err() {
+                return;
+            }
+        }
+        tokio::select! {
+            _ = wake => {}
+            _ = tick.tick() => {}
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    for reply in handle(&server, &mut session, &line).await {
+                        if write(&mut writer, &reply).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                _ => return,
+            },
+        }
+    }
+}
+
+async fn write(writer: &mut (impl AsyncWrite + Unpin), reply: &Reply) -> std::io::Result<()> {
+    let mut buf = serde_json::to_vec(reply).expect("can serialize reply");
+    buf.push(b'\n');
+    writer.write_all(&buf).await?;
+    writer.flush().await
+}
+
+async fn login(server: &Server, hello: &Hello) -> Hello {
+    let mut agreed = server.login(hello).await;
+    agreed
+        .capabilities
+        .retain(|c| *c != Capability::Compression);
+    agreed.encodings = vec![Encoding::Json];
+    agreed
+}
+
+async fn handle(server: &Server, session: &mut Option<Hello>, line: &str) -> Vec<Reply> {
+    let line = line.trim();
+    if line.is_empty() {
+        return vec![];
+    }
+    let request = match serde_json::from_str::<Request>(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return vec![Reply::Error {
+                status: 400,
+                reason: e.to_string(),
+            }]
+        }
+    };
+    if let Request::Hello(hello) = request {
+        let agreed = login(server, &hello).await;
+        *session = Some(agreed.clone());
+        return vec![Reply::Hello(agreed)];
+    }
+    let mut replies = vec![];
+    let hello = match session {
+        Some(hello) => hello,
+        None => {
+            let agreed = login(server, &Hello::new(Capability::ALL)).await;
+            replies.push(Reply::Hello(agreed.clone()));
+            session.insert(agreed)
+        }
+    };
+    let id = hello.id.expect("can read session id");
+    replies.push(match request {
+        Request::Hello(_) => unreachable!(),
+        Request::Send { dst, data, reply } => {
+            let reply = reply.filter(|_| hello.supports(Capability::Replies));
+            match server
+                .route(dst, Message::new(id, data).with_reply(reply))
+                .await
+            {
+                Ok(mid) => Reply::Sent { mid },
+                Err(status) => status.into(),
+            }
+        }
+        Request::Join { room } => Reply::Joined {
+            room: server.join(id, &room).await,
+        },
+        Request::Leave { room } => {
+            server.leave(id, room).await;
+            Reply::Ok
+        }
+        Request::Read { mids } => {
+            server.receipt(id, mids, true).await;
+            Reply::Ok
+        }
+    });
+    replies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn lines_strip_endings() {
+        let mut lines = Lines::new(&b"a\r\nb\n\nc"[..], 8);
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("a"));
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("b"));
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some(""));
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("c"));
+        assert!(lines.next_line().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn lines_cap_length() {
+        let mut lines = Lines::new(&b"1234\n12345\n"[..], 4);
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("1234"));
+        let err = lines.next_line().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut lines = Lines::new(&b"123456789"[..], 4);
+        assert!(lines.next_line().await.is_err());
+        let mut lines = Lines::new(&b"\xff\n"[..], 4);
+        assert!(lines.next_line().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lines_survive_cancelled_partial_reads() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut lines = Lines::new(rx, 8);
+        tx.write_all(b"hel").await.unwrap();
+        let wait = Duration::from_millis(20);
+        assert!(timeout(wait, lines.next_line()).await.is_err());
+        tx.write_all(b"lo\nwor").await.unwrap();
+        let line = timeout(wait, lines.next_line()).await.unwrap().unwrap();
+        assert_eq!(line.as_deref(), Some("hello"));
+        assert!(timeout(wait, lines.next_line()).await.is_err());
+        tx.write_all(b"ld!!!!").await.unwrap();
+        assert!(timeout(wait, lines.next_line()).await.unwrap().is_err());
+    }
+}
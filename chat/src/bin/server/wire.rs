@@ -0,0 +1,128 @@
+use chat::{gunzip, gzip, Encoding, COMPRESS_THRESHOLD};
+use rocket::{
+    data::{self, FromData, ToByteUnit},
+    http::{ContentType, Header, Status},
+    response::{self, Responder},
+    Data, Request, Response,
+};
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+const LIMIT: u64 = 16 << 20;
+
+#[derive(Default)]
+pub struct Metrics {
+    payloads: AtomicU64,
+    compressed: AtomicU64,
+    raw_bytes: AtomicU64,
+    wire_bytes: AtomicU64,
+}
+impl Metrics {
+    pub fn record(&self, raw: usize, wire: usize) {
+        self.payloads.fetch_add(1, Ordering::Relaxed);
+        if wire < raw {
+            self.compressed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.raw_bytes.fetch_add(raw as u64, Ordering::Relaxed);
+        self.wire_bytes.fetch_add(wire as u64, Ordering::Relaxed);
+    }
+    pub fn render(&self) -> String {
+        let raw = self.raw_bytes.load(Ordering::Relaxed);
+        let wire = self.wire_bytes.load(Ordering::Relaxed);
+        let saved = raw.saturating_sub(wire);
+        let ratio = if raw == 0 {
+            0.0
+        } else {
+            saved as f64 / raw as f64
+        };
+        format!(
+            "chat_payloads_total {}\n\
+             chat_payloads_compressed_total {}\n\
+             chat_payload_raw_bytes_total {raw}\n\
+             chat_payload_wire_bytes_total {wire}\n\
+             chat_payload_saved_bytes_total {saved}\n\
+             chat_payload_saved_ratio {ratio:.4}\n",
+            self.payloads.load(Ordering::Relaxed),
+            self.compressed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+pub struct Wire<T>(pub T);
+
+fn encoding(content_type: Option<&ContentType>) -> Encoding {
+    content_type.map_or(Encoding::Json, |ct| {
+        Encoding::from_content_type(&ct.to_string())
+    })
+}
+
+fn gzipped(req: &Request<'_>, header: &str) -> bool {
+    req.headers()
+        .get(header)
+        .any(|value| value.split(',').any(|e| e.trim().starts_with("gzip")))
+}
+
+#[rocket::async_trait]
+impl<'r, T: serde::de::DeserializeOwned> FromData<'r> for Wire<T> {
+    type Error = String;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("wire").unwrap_or(LIMIT.bytes());
+        let buf = match data.open(limit).into_bytes().await {
+            Ok(buf) if buf.is_complete() => buf.into_inner(),
+            Ok(_) => {
+                return data::Outcome::Failure((Status::PayloadTooLarge, "too large".to_string()))
+            }
+            Err(e) => return data::Outcome::Failure((Status::BadRequest, e.to_string())),
+        };
+        let buf = if gzipped(req, "Content-Encoding") {
+            match gunzip(&buf, limit.as_u64()) {
+                Ok(raw) => {
+                    if let Some(metrics) = req.rocket().state::<Metrics>() {
+                        metrics.record(raw.len(), buf.len());
+                    }
+                    raw
+                }
+                Err(e) => return data::Outcome::Failure((Status::BadRequest, e)),
+            }
+        } else {
+            buf
+        };
+        match encoding(req.content_type()).decode(&buf) {
+            Ok(value) => data::Outcome::Success(Wire(value)),
+            Err(e) => data::Outcome::Failure((Status::UnprocessableEntity, e)),
+        }
+    }
+}
+
+impl<'r, T: serde::Serialize> Responder<'r, 'static> for Wire<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let encoding = req.accept().map_or(Encoding::Json, |accept| {
+            Encoding::from_content_type(&accept.preferred().media_type().to_string())
+        });
+        let raw = encoding.encode(&self.0);
+        let content_type = match encoding {
+            Encoding::MessagePack => ContentType::MsgPack,
+            _ => ContentType::JSON,
+        };
+        let mut response = Response::build();
+        response.header(content_type);
+        let buf = if raw.len() >= COMPRESS_THRESHOLD && gzipped(req, "Accept-Encoding") {
+            let packed = gzip(&raw);
+            if let Some(metrics) = req.rocket().state::<Metrics>() {
+                metrics.record(raw.len(), packed.len().min(raw.len()));
+            }
+            if packed.len() < raw.len() {
+                response.header(Header::new("Content-Encoding", "gzip"));
+                packed
+            } else {
+                raw
+            }
+        } else {
+            raw
+        };
+        response.sized_body(buf.len(), Cursor::new(buf)).ok()
+    }
+}
@@ -1,39 +1,56 @@
 mod action;
+mod command;
+mod complete;
+mod composer;
 mod conn;
+mod headless;
+mod keymap;
+mod markup;
+mod outbox;
+pub mod sdk;
+mod store;
+mod theme;
 mod ui;
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
-use std::error::Error;
+mod wrap;
+use crossterm::event::{self, Event, KeyEventKind};
+use std::{error::Error, path::PathBuf};
+
+pub use headless::run as headless;
+
+fn data_dir() -> PathBuf {
+    std::env::var_os("CHAT_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".chat")))
+        .unwrap_or_else(|| PathBuf::from(".chat"))
+}
+
+fn account() -> String {
+    std::env::var("CHAT_ACCOUNT").unwrap_or_else(|_| "default".to_string())
+}
 
 pub async fn run() -> Result<(), Box<dyn Error>> {
     // setup termina
-    let id = conn::new().await?;
     let (tx, rx) = tokio::sync::mpsc::channel(32);
     let (tx1, rx1) = tokio::sync::mpsc::channel(32);
-    tokio::spawn(async move {
-        ui::Ui::new(id, rx, tx1).run().await;
+    let ui = tokio::spawn(async move {
+        ui::Ui::new(rx, tx1).run().await;
     });
     let usetx = tx.clone();
-    tokio::spawn(conn::run(id, rx1, usetx));
+    tokio::spawn(conn::run(rx1, usetx));
     // create app and run it
-    // ?;
-    loop {
-        // ?;
-        let event = event::read().unwrap();
-        // ?;
-        if let Event::Key(key) = event {
-            if key.kind == KeyEventKind::Press {
-                match key.code {
-                    KeyCode::Esc => {
-                        tx.send(action::Action::Over).await.unwrap();
-                        break;
-                    }
-                    _ => {
-                        tx.send(action::Action::Event(event)).await.unwrap();
-                    }
-                }
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            let action = match event {
+                Event::Key(key) if key.kind == KeyEventKind::Press => action::Action::Event(event),
+                Event::Mouse(_) => action::Action::Event(event),
+                _ => continue,
+            };
+            if tx.blocking_send(action).is_err() {
+                break;
             }
         }
-    }
+    });
+    ui.await?;
     // restore terminal
 
     Ok(())
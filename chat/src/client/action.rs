@@ -1,7 +1,22 @@
+use super::outbox::{Delivery, Outgoing};
 pub enum Action {
     Receive(crate::Message),
     Event(crossterm::event::Event),
-    Over,
+    Login(crate::Hello),
+    Status(super::conn::Status),
+    Queued(Outgoing),
+    Sent(u64, u64),
+    Delivery(u64, Delivery),
+    Joined(u32, String),
+    Notice(String),
     #[cfg(debug_assertions)]
     Err(String),
 }
+
+pub enum Request {
+    Send(Outgoing),
+    Join(String),
+    Resolve(String),
+    Leave(u32),
+    Read(Vec<u64>),
+}
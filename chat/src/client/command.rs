@@ -0,0 +1,309 @@
+pub struct Spec {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub min: usize,
+    pub max: usize,
+    pub rest: bool,
+}
+
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "help",
+        usage: "/help [command]",
+        help: "list commands or show how to use one",
+        min: 0,
+        max: 1,
+        rest: false,
+    },
+    Spec {
+        name: "file",
+        usage: "/file <path>",
+        help: "send a file to the current conversation",
+        min: 1,
+        max: 1,
+        rest: true,
+    },
+    Spec {
+        name: "open",
+        usage: "/open <id|user@server>",
+        help: "open the conversation with an id or a federated address",
+        min: 1,
+        max: 1,
+        rest: false,
+    },
+    Spec {
+        name: "nick",
+        usage: "/nick <name>",
+        help: "name the current conversation",
+        min: 1,
+        max: 1,
+        rest: true,
+    },
+    Spec {
+        name: "join",
+        usage: "/join <room>",
+        help: "join a room, creating it if needed",
+        min: 1,
+        max: 1,
+        rest: false,
+    },
+    Spec {
+        name: "leave",
+        usage: "/leave",
+        help: "leave the current room or close the conversation",
+        min: 0,
+        max: 0,
+        rest: false,
+    },
+    Spec {
+        name: "edit",
+        usage: "/edit <text>",
+        help: "replace the text of your last message",
+        min: 1,
+        max: 1,
+        rest: true,
+    },
+    Spec {
+        name: "delete",
+        usage: "/delete",
+        help: "delete your last message",
+        min: 0,
+        max: 0,
+        rest: false,
+    },
+    Spec {
+        name: "react",
+        usage: "/react <emoji>",
+        help: "toggle a reaction on the picked or last message",
+        min: 1,
+        max: 1,
+        rest: false,
+    },
+    Spec {
+        name: "clear",
+        usage: "/clear",
+        help: "clear the history of the current conversation",
+        min: 0,
+        max: 0,
+        rest: false,
+    },
+    Spec {
+        name: "quit",
+        usage: "/quit",
+        help: "exit the client",
+        min: 0,
+        max: 0,
+        rest: false,
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help(Option<String>),
+    File(String),
+    Open(u32),
+    Resolve(String),
+    Nick(String),
+    Join(String),
+    Leave,
+    Edit(String),
+    Delete,
+    React(String),
+    Clear,
+    Quit,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Text(String),
+    Command(Command),
+}
+
+pub fn find(name: &str) -> Option<&'static Spec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+pub fn split(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.push(c);
+                    started = true;
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    args.push(std::mem::take(&mut arg));
+                    started = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        args.push(arg);
+    }
+    args
+}
+
+pub fn parse(line: &str) -> Result<Input, String> {
+    let Some(rest) = line.strip_prefix('/') else {
+        return Ok(Input::Text(line.to_string()));
+    };
+    if rest.starts_with('/') {
+        return Ok(Input::Text(rest.to_string()));
+    }
+    let name = rest.split_whitespace().next().unwrap_or("");
+    if name.is_empty() {
+        return Err("empty command, try /help".to_string());
+    }
+    let spec = find(name).ok_or_else(|| format!("unknown command /{name}, try /help"))?;
+    let tail = rest[name.len()..].trim();
+    let args = if !spec.rest {
+        split(tail)
+    } else if tail.is_empty() {
+        vec![]
+    } else {
+        vec![tail.to_string()]
+    };
+    if args.len() < spec.min || args.len() > spec.max {
+        return Err(format!("usage: {}", spec.usage));
+    }
+    let mut args = args.into_iter();
+    let command = match spec.name {
+        "help" => Command::Help(args.next()),
+        "file" => Command::File(args.next().unwrap()),
+        "open" => match args.next().unwrap() {
+            addr if addr.contains('@') => Command::Resolve(addr),
+            id => Command::Open(id.parse().map_err(|_| format!("usage: {}", spec.usage))?),
+        },
+        "nick" => Command::Nick(args.next().unwrap()),
+        "join" => Command::Join(args.next().unwrap()),
+        "leave" => Command::Leave,
+        "edit" => Command::Edit(args.next().unwrap()),
+        "delete" => Command::Delete,
+        "react" => Command::React(args.next().unwrap()),
+        "clear" => Command::Clear,
+        "quit" => Command::Quit,
+        _ => unreachable!(),
+    };
+    Ok(Input::Command(command))
+}
+
+pub fn help(name: Option<&str>) -> String {
+    match name {
+        Some(name) => match find(name.trim_start_matches('/')) {
+            Some(spec) => format!("{}: {}", spec.usage, spec.help),
+            None => format!("unknown command /{name}"),
+        },
+        None => format!(
+            "{}, // sends a literal /",
+            COMMANDS
+                .iter()
+                .map(|spec| format!("/{}", spec.name))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        match parse(line) {
+            Ok(Input::Command(command)) => command,
+            other => panic!("{line}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn split_quotes_and_escapes() {
+        assert_eq!(split("  a  b "), vec!["a", "b"]);
+        assert_eq!(split(r#"a "b c" d"#), vec!["a", "b c", "d"]);
+        assert_eq!(split(r#""" x"#), vec!["", "x"]);
+        assert_eq!(split(r#"a\ b \"c"#), vec!["a b", "\"c"]);
+    }
+
+    #[test]
+    fn text_and_escaped_slash() {
+        assert_eq!(parse("hello"), Ok(Input::Text("hello".to_string())));
+        assert_eq!(parse("//help"), Ok(Input::Text("/help".to_string())));
+        assert_eq!(parse("// x"), Ok(Input::Text("/ x".to_string())));
+    }
+
+    #[test]
+    fn commands_and_arguments() {
+        assert_eq!(command("/help"), Command::Help(None));
+        assert_eq!(
+            command("/help file"),
+            Command::Help(Some("file".to_string()))
+        );
+        assert_eq!(
+            command("/file ~/a b.txt"),
+            Command::File("~/a b.txt".to_string())
+        );
+        assert_eq!(
+            command("/join \"my room\""),
+            Command::Join("my room".to_string())
+        );
+        assert_eq!(command("/open 7"), Command::Open(7));
+        assert_eq!(
+            command("/open 2@beta"),
+            Command::Resolve("2@beta".to_string())
+        );
+        assert_eq!(
+            command("/edit  fixed  text "),
+            Command::Edit("fixed  text".to_string())
+        );
+        assert_eq!(command("/quit"), Command::Quit);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("/"), Err("empty command, try /help".to_string()));
+        assert_eq!(
+            parse("/nope"),
+            Err("unknown command /nope, try /help".to_string())
+        );
+        assert_eq!(
+            parse("/open"),
+            Err("usage: /open <id|user@server>".to_string())
+        );
+        assert_eq!(
+            parse("/open x"),
+            Err("usage: /open <id|user@server>".to_string())
+        );
+        assert_eq!(
+            parse("/open 1 2"),
+            Err("usage: /open <id|user@server>".to_string())
+        );
+        assert_eq!(parse("/quit now"), Err("usage: /quit".to_string()));
+        assert_eq!(parse("/edit"), Err("usage: /edit <text>".to_string()));
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        let all = help(None);
+        assert!(COMMANDS
+            .iter()
+            .all(|spec| all.contains(&format!("/{}", spec.name))));
+        assert_eq!(help(Some("/quit")), "/quit: exit the client");
+        assert_eq!(help(Some("x")), "unknown command /x");
+    }
+}
@@ -0,0 +1,118 @@
+use super::command::COMMANDS;
+use std::{fs, path::PathBuf};
+
+pub struct Completion {
+    pub start: usize,
+    pub end: usize,
+    pub candidates: Vec<String>,
+    pub index: Option<usize>,
+}
+
+fn commands(prefix: &str, slash: &str) -> Vec<String> {
+    COMMANDS
+        .iter()
+        .filter(|spec| spec.name.starts_with(prefix))
+        .map(|spec| format!("{slash}{}", spec.name))
+        .collect()
+}
+
+fn contacts(prefix: &str, list: &[(u32, Option<String>)], ids: bool) -> Vec<String> {
+    let mut found = vec![];
+    for (id, name) in list {
+        if ids && id.to_string().starts_with(prefix) {
+            found.push(id.to_string());
+        } else if let Some(name) = name.as_ref().filter(|name| name.starts_with(prefix)) {
+            found.push(if ids { id.to_string() } else { name.clone() });
+        }
+    }
+    found
+}
+
+pub fn expand(path: &str) -> PathBuf {
+    let rest = match path.strip_prefix('~') {
+        Some("") => "",
+        Some(rest) if rest.starts_with('/') => &rest[1..],
+        _ => return PathBuf::from(path),
+    };
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(rest))
+        .unwrap_or_else(|| PathBuf::from(path))
+}
+
+fn paths(arg: &str) -> Vec<String> {
+    let (dir, prefix) = match arg.rsplit_once('/') {
+        Some((dir, prefix)) => (format!("{dir}/"), prefix),
+        None => (String::new(), arg),
+    };
+    let read = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand(&dir)
+    };
+    let Ok(entries) = fs::read_dir(read) else {
+        return vec![];
+    };
+    let mut found = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    found
+}
+
+pub fn candidates(
+    text: &str,
+    cursor: usize,
+    list: &[(u32, Option<String>)],
+) -> (usize, Vec<String>) {
+    let head = &text[..cursor];
+    let start = head.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &head[start..];
+    if let Some(rest) = head.strip_prefix('/') {
+        if start == 0 {
+            return (0, commands(rest, "/"));
+        }
+        let name = rest.split_whitespace().next().unwrap_or("");
+        return match name {
+            "file" => {
+                let arg = rest[name.len()..].trim_start();
+                (cursor - arg.len(), paths(arg))
+            }
+            "open" => (start, contacts(word, list, true)),
+            "help" => (start, commands(word, "")),
+            _ => (start, vec![]),
+        };
+    }
+    match word.strip_prefix('@') {
+        Some(name) => (
+            start,
+            contacts(name, list, false)
+                .into_iter()
+                .map(|name| format!("@{name}"))
+                .collect(),
+        ),
+        None => (start, vec![]),
+    }
+}
+
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(candidate.len()), |((i, _), _)| i.min(len));
+    }
+    first[..len].to_string()
+}
@@ -0,0 +1,107 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthChar;
+
+pub struct Composer {
+    text: String,
+    cursor: usize,
+}
+impl Composer {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+        }
+    }
+    pub fn value(&self) -> &str {
+        &self.text
+    }
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+    pub fn replace(&mut self, range: std::ops::Range<usize>, with: &str) {
+        self.cursor = range.start + with.len();
+        self.text.replace_range(range, with);
+    }
+    pub fn reset(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+    fn prev(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .last()
+            .map_or(0, |(i, _)| i)
+    }
+    fn next(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+    pub fn handle(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => self.insert('\n'),
+            KeyCode::Char('j') if ctrl => self.insert('\n'),
+            KeyCode::Char('u') if ctrl => self.reset(),
+            KeyCode::Char(c) if !ctrl => self.insert(c),
+            KeyCode::Backspace if self.cursor > 0 => {
+                let prev = self.prev();
+                self.text.replace_range(prev..self.cursor, "");
+                self.cursor = prev;
+            }
+            KeyCode::Delete if self.cursor < self.text.len() => {
+                let next = self.next();
+                self.text.replace_range(self.cursor..next, "");
+            }
+            KeyCode::Left => self.cursor = self.prev(),
+            KeyCode::Right => self.cursor = self.next(),
+            KeyCode::Home => {
+                self.cursor = self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1);
+            }
+            KeyCode::End => {
+                self.cursor = self.text[self.cursor..]
+                    .find('\n')
+                    .map_or(self.text.len(), |i| self.cursor + i);
+            }
+            _ => return false,
+        }
+        true
+    }
+    pub fn layout(&self, width: usize) -> (Vec<String>, (usize, usize)) {
+        let width = width.max(1);
+        let mut lines = vec![String::new()];
+        let mut x = 0;
+        let mut cursor = None;
+        for (i, c) in self.text.char_indices() {
+            if i == self.cursor {
+                cursor = Some((x, lines.len() - 1));
+            }
+            if c == '\n' {
+                lines.push(String::new());
+                x = 0;
+                continue;
+            }
+            let w = c.width().unwrap_or(0);
+            if x + w > width {
+                lines.push(String::new());
+                x = 0;
+            }
+            lines.last_mut().unwrap().push(c);
+            x += w;
+        }
+        let cursor = cursor.unwrap_or_else(|| {
+            if x >= width {
+                lines.push(String::new());
+                (0, lines.len() - 1)
+            } else {
+                (x, lines.len() - 1)
+            }
+        });
+        (lines, cursor)
+    }
+}
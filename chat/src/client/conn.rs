@@ -1,78 +1,210 @@
-use super::action::Action;
-use crate::Message;
-use rocket::serde::json::Json;
-use std::error::Error;
+use super::{
+    action::{Action, Request},
+    outbox::{Delivery, Outbox},
+    sdk::{Client, SdkError},
+};
+use reqwest::StatusCode;
+use std::collections::VecDeque;
 use tokio::{
     sync::mpsc,
-    time::{timeout, Duration},
+    time::{sleep, timeout, Duration},
 };
-pub struct Conn {
-    client: reqwest::Client,
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+    Connecting,
+    Online,
+    Degraded,
+    Offline,
 }
-impl Conn {
-    pub async fn new() -> Self {
-        Self {
-            client: reqwest::Client::new(),
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Connecting => "connecting",
+            Status::Online => "online",
+            Status::Degraded => "degraded",
+            Status::Offline => "offline",
         }
     }
-    pub async fn send(&self, dst: u32, msg: &Message) -> Result<(), reqwest::Error> {
-        self.client
-            .post(format!("http://localhost:8000/send?dst={}", dst))
-            .json(msg)
-            .send()
-            .await?;
-        Ok(())
+}
+
+struct Backoff {
+    failures: u32,
+}
+impl Backoff {
+    const BASE: Duration = Duration::from_millis(200);
+    const MAX: Duration = Duration::from_secs(10);
+    const OFFLINE_AFTER: u32 = 3;
+    pub fn new() -> Self {
+        Self { failures: 0 }
+    }
+    pub fn reset(&mut self) {
+        self.failures = 0;
     }
-    pub async fn recv(&self, id: u32) -> Result<Vec<Message>, reqwest::Error> {
-        let resp = self
-            .client
-            .get(format!("http://localhost:8000/recv?dst={}", id))
-            .send()
-            .await?;
-        let msgs = resp.json::<Vec<Message>>().await?;
-        Ok(msgs)
+    pub fn fail(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        Self::BASE
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(Self::MAX)
+    }
+    pub fn status(&self) -> Status {
+        match self.failures {
+            0 => Status::Online,
+            n if n < Self::OFFLINE_AFTER => Status::Degraded,
+            _ => Status::Offline,
+        }
     }
 }
 
-pub async fn new() -> Result<u32, Box<dyn Error>> {
-    let msg = reqwest::get("http://localhost:8000/")
-        .await?
-        .text()
-        .await?
-        .parse::<u32>()?;
-    Ok(msg)
+struct Link {
+    status: Status,
+    backoff: Backoff,
+    tx: mpsc::Sender<Action>,
 }
-pub async fn run(id: u32, mut rx: mpsc::Receiver<Json<Message>>, tx: mpsc::Sender<Action>) {
-    let conn = Conn::new().await;
+impl Link {
+    pub fn new(tx: mpsc::Sender<Action>) -> Self {
+        Self {
+            status: Status::Connecting,
+            backoff: Backoff::new(),
+            tx,
+        }
+    }
+    async fn report(&mut self, status: Status) {
+        if self.status != status {
+            self.status = status;
+            let _ = self.tx.send(Action::Status(status)).await;
+        }
+    }
+    pub async fn ok(&mut self) {
+        self.backoff.reset();
+        self.report(Status::Online).await;
+    }
+    pub async fn fail(&mut self, err: String) {
+        let delay = self.backoff.fail();
+        let status = match self.status {
+            Status::Connecting if self.backoff.status() != Status::Offline => Status::Connecting,
+            _ => self.backoff.status(),
+        };
+        self.report(status).await;
+        #[cfg(debug_assertions)]
+        let _ = self.tx.send(Action::Err(err)).await;
+        #[cfg(not(debug_assertions))]
+        let _ = err;
+        sleep(delay).await;
+    }
+}
+
+pub async fn run(mut rx: mpsc::Receiver<Request>, tx: mpsc::Sender<Action>) {
+    let client = Client::default();
+    let mut link = Link::new(tx.clone());
+    let passphrase = std::env::var("CHAT_PASSPHRASE").ok();
+    let mut outbox = Outbox::load(&super::account(), passphrase.as_deref()).unwrap_or_else(|e| {
+        let _ = tx.try_send(Action::Notice(format!("outbox not loaded: {e}")));
+        Outbox::memory()
+    });
+    let _ = tx.send(Action::Status(Status::Connecting)).await;
+    for msg in outbox.iter() {
+        let _ = tx.send(Action::Queued(msg.clone())).await;
+    }
+    let mut requests = VecDeque::new();
+    let mut connected = None;
     loop {
         match timeout(Duration::from_millis(100), rx.recv()).await {
-            Ok(Some(msg)) => match conn.send(msg.id, &Message::new(id, msg.data.clone())).await {
-                Ok(_) => {}
+            Ok(Some(Request::Send(msg))) => outbox.push(msg),
+            Ok(Some(request)) => requests.push_back(request),
+            Ok(None) => break,
+            Err(_) => {}
+        }
+        while let Ok(request) = rx.try_recv() {
+            match request {
+                Request::Send(msg) => outbox.push(msg),
+                request => requests.push_back(request),
+            }
+        }
+        if connected.is_none() {
+            match client.clone().connect().await {
+                Ok(new) => {
+                    link.ok().await;
+                    if tx.send(Action::Login(new.hello().clone())).await.is_err() {
+                        return;
+                    }
+                    connected = Some(new);
+                }
                 Err(e) => {
-                    #[cfg(debug_assertions)]
-                    tx.send(Action::Err(e.to_string())).await.unwrap();
+                    link.fail(e.to_string()).await;
+                    continue;
                 }
-            },
-            Ok(None) => {
-                #[cfg(debug_assertions)]
-                tx.send(Action::Err("recv channel closed".to_string()))
-                    .await
-                    .unwrap();
             }
-            Err(_) => {
-                // tx.send(Action::Err(e.to_string())).await.unwrap();
+        }
+        let Some(session) = &connected else {
+            continue;
+        };
+        while let Some(request) = requests.front() {
+            let result = match request {
+                Request::Join(room) => session
+                    .join(room)
+                    .await
+                    .map(|rid| Some(Action::Joined(rid, room.clone()))),
+                Request::Resolve(addr) => session
+                    .client()
+                    .resolve(addr)
+                    .await
+                    .map(|id| Some(Action::Joined(id, addr.clone()))),
+                Request::Leave(room) => session
+                    .leave(*room)
+                    .await
+                    .map(|_| Some(Action::Notice(format!("left {room}")))),
+                Request::Read(mids) => session.read(mids).await.map(|_| None),
+                Request::Send(_) => unreachable!(),
+            };
+            match result {
+                Ok(action) => {
+                    link.ok().await;
+                    requests.pop_front();
+                    if let Some(action) = action {
+                        let _ = tx.send(action).await;
+                    }
+                }
+                Err(e) if e.is_transient() => {
+                    link.fail(e.to_string()).await;
+                    break;
+                }
+                Err(e) => {
+                    requests.pop_front();
+                    let _ = tx.send(Action::Notice(e.to_string())).await;
+                }
             }
         }
-        match conn.recv(id).await {
+        while let Some(msg) = outbox.front() {
+            let seq = msg.seq;
+            let action = match session.send(msg.dst, msg.data.clone(), msg.reply).await {
+                Ok(mid) => {
+                    link.ok().await;
+                    Action::Sent(seq, mid)
+                }
+                Err(e) if e.is_transient() => {
+                    link.fail(e.to_string()).await;
+                    break;
+                }
+                Err(_) => Action::Delivery(seq, Delivery::Failed),
+            };
+            outbox.pop();
+            let _ = tx.send(action).await;
+        }
+        match session.recv().await {
             Ok(msgs) => {
+                link.ok().await;
                 for msg in msgs {
-                    tx.send(Action::Receive(msg)).await.unwrap();
+                    if tx.send(Action::Receive(msg)).await.is_err() {
+                        return;
+                    }
                 }
             }
-            Err(e) => {
-                #[cfg(debug_assertions)]
-                tx.send(Action::Err(e.to_string())).await.unwrap();
+            Err(SdkError::Status(StatusCode::NOT_FOUND)) => {
+                connected = None;
+                link.fail("server forgot the session".to_string()).await;
             }
+            Err(e) => link.fail(e.to_string()).await,
         }
     }
 }
@@ -0,0 +1,138 @@
+use super::sdk::{Client, Session};
+use futures_util::StreamExt;
+use std::{
+    error::Error,
+    io::{stdout, Write},
+};
+use tokio::{
+    io::{stdin, AsyncBufReadExt, BufReader},
+    time::Duration,
+};
+
+pub const USAGE: &str = "usage:
+  cli                                         start the chat client
+  cli send --to <id> [--from <id>] [text...]  send text, or each stdin line without text
+  cli send-file --to <id> [--from <id>] <path>
+                                              --to also takes a federated user@server
+  cli listen [--id <id>] [--json]             print incoming messages";
+
+#[derive(Default)]
+struct Args {
+    to: Option<String>,
+    from: Option<u32>,
+    json: bool,
+    rest: Vec<String>,
+}
+impl Args {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut id = || {
+                args.next()
+                    .filter(|id| id.parse::<u32>().is_ok() || id.contains('@'))
+                    .ok_or_else(|| format!("{arg} needs an id"))
+            };
+            match arg.as_str() {
+                "--to" => parsed.to = Some(id()?.clone()),
+                "--from" | "--id" => {
+                    parsed.from = Some(id()?.parse().map_err(|_| format!("{arg} needs an id"))?)
+                }
+                "--json" => parsed.json = true,
+                "--" => parsed.rest.extend(args.by_ref().cloned()),
+                flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}")),
+                _ => parsed.rest.push(arg.clone()),
+            }
+        }
+        Ok(parsed)
+    }
+    pub async fn to(&self, session: &Session) -> Result<u32, Box<dyn Error>> {
+        let to = self.to.as_deref().ok_or("missing --to <id>")?;
+        match to.parse() {
+            Ok(id) => Ok(id),
+            Err(_) => Ok(session.client().resolve(to).await?),
+        }
+    }
+}
+
+async fn login(id: Option<u32>) -> Result<Session, Box<dyn Error>> {
+    let client = Client::default();
+    match id {
+        Some(id) => Ok(client.session(id)),
+        None => {
+            let session = client.connect().await?;
+            eprintln!("logged in as {}", session.id());
+            Ok(session)
+        }
+    }
+}
+
+async fn listen(session: Session, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut out = stdout();
+    let mut events = Box::pin(session.events(Duration::from_millis(200)));
+    while let Some(msg) = events.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) if e.is_transient() => {
+                eprintln!("{e}");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let line = if json {
+            serde_json::to_string(&msg)?
+        } else if msg.data.is_event() {
+            continue;
+        } else {
+            msg.to_string()
+        };
+        if writeln!(out, "{line}").and_then(|_| out.flush()).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let Some((command, args)) = args.split_first() else {
+        return Err(USAGE.into());
+    };
+    let args = Args::parse(args)?;
+    match command.as_str() {
+        "send" => {
+            args.to.as_ref().ok_or("missing --to <id>")?;
+            let session = login(args.from).await?;
+            let dst = args.to(&session).await?;
+            if !args.rest.is_empty() {
+                println!("{}", session.send_text(dst, args.rest.join(" ")).await?);
+                return Ok(());
+            }
+            let mut lines = BufReader::new(stdin()).lines();
+            while let Some(line) = lines.next_line().await? {
+                if !line.is_empty() {
+                    println!("{}", session.send_text(dst, line).await?);
+                }
+            }
+            Ok(())
+        }
+        "send-file" => {
+            args.to.as_ref().ok_or("missing --to <id>")?;
+            let [path] = &args.rest[..] else {
+                return Err(USAGE.into());
+            };
+            if let Err(e) = tokio::fs::metadata(path).await {
+                return Err(format!("can't read {path}: {e}").into());
+            }
+            let session = login(args.from).await?;
+            let dst = args.to(&session).await?;
+            println!("{}", session.send_file(dst, path).await?);
+            Ok(())
+        }
+        "listen" => listen(login(args.from).await?, args.json).await,
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
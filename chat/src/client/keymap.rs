@@ -0,0 +1,140 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{collections::HashMap, fs};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bind {
+    Quit,
+    Complete,
+    FocusNext,
+    FocusPrevious,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Top,
+    Bottom,
+    Reply,
+    React,
+}
+
+const BINDS: &[(&str, Bind, &[&str])] = &[
+    ("quit", Bind::Quit, &["esc"]),
+    ("complete", Bind::Complete, &["tab"]),
+    ("focus_next", Bind::FocusNext, &["tab"]),
+    ("focus_previous", Bind::FocusPrevious, &["backtab"]),
+    ("page_up", Bind::PageUp, &["pageup"]),
+    ("page_down", Bind::PageDown, &["pagedown"]),
+    ("up", Bind::Up, &["up"]),
+    ("down", Bind::Down, &["down"]),
+    ("top", Bind::Top, &["home"]),
+    ("bottom", Bind::Bottom, &["end"]),
+    ("reply", Bind::Reply, &["enter", "r"]),
+    ("react", Bind::React, &["+"]),
+];
+
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> (KeyCode, KeyModifiers) {
+    match code {
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+            (KeyCode::BackTab, modifiers - KeyModifiers::SHIFT)
+        }
+        KeyCode::Char(_) | KeyCode::BackTab => (code, modifiers - KeyModifiers::SHIFT),
+        _ => (code, modifiers),
+    }
+}
+
+fn parse(key: &str) -> Result<(KeyCode, KeyModifiers), String> {
+    let (mods, name) = if key == "+" {
+        ("", "+")
+    } else if let Some(mods) = key.strip_suffix("++") {
+        (mods, "+")
+    } else {
+        key.rsplit_once('+').unwrap_or(("", key))
+    };
+    let mut modifiers = KeyModifiers::NONE;
+    for m in mods.split('+').filter(|m| !m.is_empty()) {
+        modifiers |= match m.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(format!("keymap: unknown modifier {m} in {key}")),
+        };
+    }
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match name.to_lowercase().as_str() {
+            "esc" => KeyCode::Esc,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            f => match f.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n) => KeyCode::F(n),
+                None => return Err(format!("keymap: unknown key {key}")),
+            },
+        },
+    };
+    Ok(normalize(code, modifiers))
+}
+
+pub struct Keymap {
+    keys: Vec<(Bind, KeyCode, KeyModifiers)>,
+}
+impl Keymap {
+    fn bind(&mut self, bind: Bind, keys: &[&str]) -> Result<(), String> {
+        self.keys.retain(|(b, _, _)| *b != bind);
+        for key in keys {
+            let (code, modifiers) = parse(key)?;
+            self.keys.push((bind, code, modifiers));
+        }
+        Ok(())
+    }
+    pub fn new() -> Self {
+        let mut keymap = Self { keys: vec![] };
+        for (_, bind, keys) in BINDS {
+            keymap.bind(*bind, keys).expect("can parse default keys");
+        }
+        keymap
+    }
+    pub fn load() -> Result<Self, String> {
+        let mut keymap = Self::new();
+        let path = super::data_dir().join("keymap.json");
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(keymap),
+            Err(e) => return Err(format!("keymap {}: {e}", path.display())),
+        };
+        let config = serde_json::from_slice::<HashMap<String, serde_json::Value>>(&buf)
+            .map_err(|e| format!("keymap {}: {e}", path.display()))?;
+        for (name, keys) in config {
+            let (_, bind, _) = BINDS
+                .iter()
+                .find(|(n, _, _)| *n == name)
+                .ok_or_else(|| format!("keymap: unknown action {name}"))?;
+            let keys = match &keys {
+                serde_json::Value::String(key) => vec![key.as_str()],
+                serde_json::Value::Array(keys) => keys.iter().filter_map(|k| k.as_str()).collect(),
+                _ => return Err(format!("keymap: keys for {name} must be a string or list")),
+            };
+            keymap.bind(*bind, &keys)?;
+        }
+        Ok(keymap)
+    }
+    pub fn is(&self, key: &KeyEvent, bind: Bind) -> bool {
+        let (code, modifiers) = normalize(key.code, key.modifiers);
+        self.keys
+            .iter()
+            .any(|&(b, c, m)| b == bind && c == code && m == modifiers)
+    }
+}
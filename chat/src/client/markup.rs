@@ -0,0 +1,251 @@
+use super::{theme::Theme, wrap::wrap_ranges};
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span},
+};
+use std::ops::Range;
+
+pub struct Styled {
+    pub text: String,
+    runs: Vec<(Range<usize>, Style)>,
+}
+impl Styled {
+    pub fn plain(text: &str, style: Style) -> Self {
+        Self {
+            text: text.to_string(),
+            runs: vec![(0..text.len(), style)],
+        }
+    }
+    fn push(&mut self, text: &str, style: Style) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        match self.runs.last_mut() {
+            Some((range, last)) if *last == style && range.end == start => {
+                range.end = self.text.len()
+            }
+            _ => self.runs.push((start..self.text.len(), style)),
+        }
+    }
+    pub fn lines(&self, width: usize) -> Vec<Line<'static>> {
+        wrap_ranges(&self.text, width)
+            .into_iter()
+            .map(|line| {
+                Line::from(
+                    self.runs
+                        .iter()
+                        .filter(|(range, _)| range.start < line.end && range.end > line.start)
+                        .map(|(range, style)| {
+                            let text =
+                                &self.text[range.start.max(line.start)..range.end.min(line.end)];
+                            Span::styled(text.to_string(), *style)
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect()
+    }
+}
+
+fn word_start(chars: &[char], i: usize) -> bool {
+    i == 0 || chars[i - 1].is_whitespace() || chars[i - 1] == '('
+}
+
+fn find(chars: &[char], from: usize, pat: &str) -> Option<usize> {
+    let pat = pat.chars().collect::<Vec<_>>();
+    (from..chars.len()).find(|&j| chars[j..].starts_with(&pat))
+}
+
+fn inline(out: &mut Styled, line: &str, base: Style, theme: &Theme, me: &[String]) {
+    let chars = line.chars().collect::<Vec<_>>();
+    let (mut bold, mut italic) = (false, None::<char>);
+    let mut i = 0;
+    while i < chars.len() {
+        let mut style = base;
+        if bold {
+            style = style.bold();
+        }
+        if italic.is_some() {
+            style = style.italic();
+        }
+        let c = chars[i];
+        let rest = &chars[i..];
+        if c == '\\' && chars.get(i + 1).is_some_and(|c| c.is_ascii_punctuation()) {
+            out.push(&chars[i + 1].to_string(), style);
+            i += 2;
+        } else if let Some(end) = (c == '`').then(|| find(&chars, i + 1, "`")).flatten() {
+            let code = chars[i + 1..end].iter().collect::<String>();
+            out.push(&code, base.fg(theme.code_fg).bg(theme.code_bg));
+            i = end + 1;
+        } else if rest.starts_with(&['*', '*']) && (bold || find(&chars, i + 2, "**").is_some()) {
+            bold = !bold;
+            i += 2;
+        } else if (c == '*' || c == '_')
+            && match italic {
+                Some(open) => open == c && !chars.get(i + 1).is_some_and(|c| c.is_alphanumeric()),
+                None => {
+                    word_start(&chars, i)
+                        && chars
+                            .get(i + 1)
+                            .is_some_and(|&n| !n.is_whitespace() && n != c)
+                        && find(&chars, i + 1, &c.to_string()).is_some()
+                }
+            }
+        {
+            italic = if italic.is_some() { None } else { Some(c) };
+            i += 1;
+        } else if let Some((text_end, url_end)) = (c == '[')
+            .then(|| find(&chars, i + 1, "]("))
+            .flatten()
+            .and_then(|mid| Some((mid, find(&chars, mid + 2, ")")?)))
+        {
+            let text = chars[i + 1..text_end].iter().collect::<String>();
+            let url = chars[text_end + 2..url_end].iter().collect::<String>();
+            out.push(&text, style.fg(theme.accent).underlined());
+            out.push(&format!(" ({url})"), style.fg(theme.muted));
+            i = url_end + 1;
+        } else if word_start(&chars, i)
+            && (rest.starts_with(&['h', 't', 't', 'p', ':', '/', '/'])
+                || rest.starts_with(&['h', 't', 't', 'p', 's', ':', '/', '/']))
+        {
+            let mut end = (i..chars.len())
+                .find(|&j| chars[j].is_whitespace())
+                .unwrap_or(chars.len());
+            while end > i && ".,;:!?)".contains(chars[end - 1]) {
+                end -= 1;
+            }
+            let url = chars[i..end].iter().collect::<String>();
+            out.push(&url, style.fg(theme.accent).underlined());
+            i = end;
+        } else if c == '@' && word_start(&chars, i) {
+            let mut end = (i + 1..chars.len())
+                .find(|&j| !(chars[j].is_alphanumeric() || "_-.".contains(chars[j])))
+                .unwrap_or(chars.len());
+            while end > i + 1 && chars[end - 1] == '.' {
+                end -= 1;
+            }
+            let name = chars[i + 1..end].iter().collect::<String>();
+            let style = if name.is_empty() {
+                style
+            } else if me.contains(&name) {
+                style.fg(theme.mark_fg).bg(theme.mark_bg).bold()
+            } else {
+                style.fg(theme.accent).bold()
+            };
+            out.push(&format!("@{name}"), style);
+            i = end;
+        } else {
+            out.push(&c.to_string(), style);
+            i += 1;
+        }
+    }
+}
+
+pub fn parse(text: &str, base: Style, theme: &Theme, me: &[String]) -> Styled {
+    let mut out = Styled {
+        text: String::new(),
+        runs: vec![],
+    };
+    let mut code = false;
+    let mut first = true;
+    for line in text.split('\n') {
+        if line.trim_start().starts_with("```") {
+            code = !code;
+            continue;
+        }
+        if !first {
+            out.push("\n", base);
+        }
+        first = false;
+        if code {
+            out.push(line, base.fg(theme.code_fg).bg(theme.code_bg));
+        } else {
+            inline(&mut out, line, base, theme, me);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(text: &str) -> Vec<(String, Style)> {
+        let styled = parse(text, Style::default(), &Theme::dark(), &["ann".to_string()]);
+        styled
+            .runs
+            .iter()
+            .map(|(range, style)| (styled.text[range.clone()].to_string(), *style))
+            .collect()
+    }
+
+    #[test]
+    fn bold_italic_and_code() {
+        let theme = Theme::dark();
+        let base = Style::default();
+        assert_eq!(
+            spans("a **b** _c_ `d*`"),
+            vec![
+                ("a ".to_string(), base),
+                ("b".to_string(), base.bold()),
+                (" ".to_string(), base),
+                ("c".to_string(), base.italic()),
+                (" ".to_string(), base),
+                ("d*".to_string(), base.fg(theme.code_fg).bg(theme.code_bg)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unmatched_markers_stay_literal() {
+        assert_eq!(
+            spans("2 ** 3"),
+            vec![("2 ** 3".to_string(), Style::default())]
+        );
+        assert_eq!(spans("snake_case_name").len(), 1);
+        assert_eq!(spans("a * b").len(), 1);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            spans(r"\*\*not bold\*\* \\"),
+            vec![("**not bold** \\".to_string(), Style::default())]
+        );
+    }
+
+    #[test]
+    fn links_and_urls() {
+        let link = Style::default().fg(Theme::dark().accent).underlined();
+        let runs = spans("see [docs](http://x.y) or http://a.b/c.");
+        let text = runs
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect::<String>();
+        assert_eq!(text, "see docs (http://x.y) or http://a.b/c.");
+        assert!(runs.contains(&("docs".to_string(), link)));
+        assert!(runs.contains(&("http://a.b/c".to_string(), link)));
+        assert_eq!(runs.last().unwrap().0, ".");
+    }
+
+    #[test]
+    fn mentions() {
+        let theme = Theme::dark();
+        let runs = spans("hi @ann and @bob. mail a@b");
+        let own = Style::default().fg(theme.mark_fg).bg(theme.mark_bg).bold();
+        let other = Style::default().fg(theme.accent).bold();
+        assert!(runs.contains(&("@ann".to_string(), own)));
+        assert!(runs.contains(&("@bob".to_string(), other)));
+        assert!(runs.iter().all(|(text, _)| text != "@b"));
+    }
+
+    #[test]
+    fn code_blocks_drop_fences() {
+        let styled = parse(
+            "a\n```\n**x**\n```\nb",
+            Style::default(),
+            &Theme::dark(),
+            &[],
+        );
+        assert_eq!(styled.text, "a\n**x**\nb");
+    }
+}
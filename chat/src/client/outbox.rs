@@ -0,0 +1,161 @@
+use super::store::{Cipher, StoreError};
+use crate::Data;
+use std::{collections::VecDeque, fs, path::PathBuf};
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub enum Delivery {
+    Pending,
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+}
+impl Delivery {
+    pub fn upgrade(self, to: Delivery) -> Delivery {
+        match (self, to) {
+            (Delivery::Failed, _) | (_, Delivery::Failed) => to,
+            _ => self.max(to),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Outgoing {
+    pub seq: u64,
+    pub dst: u32,
+    pub data: Data,
+    #[serde(default)]
+    pub reply: Option<u64>,
+}
+impl Outgoing {
+    pub fn new(seq: u64, dst: u32, data: Data) -> Self {
+        Self {
+            seq,
+            dst,
+            data,
+            reply: None,
+        }
+    }
+}
+
+pub struct Outbox {
+    dir: Option<PathBuf>,
+    cipher: Option<Cipher>,
+    queue: VecDeque<Outgoing>,
+}
+impl Outbox {
+    pub fn memory() -> Self {
+        Self {
+            dir: None,
+            cipher: None,
+            queue: VecDeque::new(),
+        }
+    }
+    pub fn load(account: &str, passphrase: Option<&str>) -> Result<Self, StoreError> {
+        let dir = super::data_dir().join("outbox").join(account);
+        let mut cipher = None;
+        let mut queue = vec![];
+        for entry in fs::read_dir(&dir).into_iter().flatten() {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let buf = fs::read(path)?;
+            let plain = match Cipher::salt(&buf) {
+                Some(salt) => {
+                    let pass = passphrase.ok_or(StoreError::Locked)?;
+                    cipher
+                        .get_or_insert_with(|| Cipher::new(pass, salt))
+                        .open(&buf)?
+                }
+                None => buf,
+            };
+            queue.push(serde_json::from_slice::<Outgoing>(&plain)?);
+        }
+        queue.sort_by_key(|msg| msg.seq);
+        let mut outbox = Self {
+            dir: Some(dir),
+            cipher: cipher.or_else(|| passphrase.map(Cipher::generate)),
+            queue: queue.into(),
+        };
+        let old = super::data_dir()
+            .join("outbox")
+            .join(format!("{account}.json"));
+        if let Ok(buf) = fs::read(&old) {
+            for msg in serde_json::from_slice::<Vec<Outgoing>>(&buf)? {
+                outbox.save(&msg)?;
+                outbox.queue.push_back(msg);
+            }
+            fs::remove_file(old)?;
+        }
+        Ok(outbox)
+    }
+    fn path(&self, seq: u64) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{seq}.json")))
+    }
+    fn save(&self, msg: &Outgoing) -> Result<(), StoreError> {
+        let Some(path) = self.path(msg.seq) else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let plain = serde_json::to_vec(msg)?;
+        let buf = match &self.cipher {
+            Some(cipher) => cipher.seal(&plain),
+            None => plain,
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Outgoing> {
+        self.queue.iter()
+    }
+    pub fn front(&self) -> Option<&Outgoing> {
+        self.queue.front()
+    }
+    pub fn push(&mut self, msg: Outgoing) {
+        let _ = self.save(&msg);
+        self.queue.push_back(msg);
+    }
+    pub fn pop(&mut self) -> Option<Outgoing> {
+        let msg = self.queue.pop_front()?;
+        if let Some(path) = self.path(msg.seq) {
+            let _ = fs::remove_file(path);
+        }
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_entries_survive_a_reload() {
+        let home = std::env::temp_dir().join(format!("chat-outbox-{}", std::process::id()));
+        std::env::set_var("CHAT_HOME", &home);
+        let mut outbox = Outbox::load("sealed", Some("pw")).unwrap();
+        outbox.push(Outgoing::new(2, 7, Data::Text("secret words".to_string())));
+        outbox.push(Outgoing::new(1, 7, Data::Text("earlier".to_string())));
+        let dir = home.join("outbox").join("sealed");
+        for entry in fs::read_dir(&dir).unwrap() {
+            let buf = fs::read(entry.unwrap().path()).unwrap();
+            assert!(Cipher::salt(&buf).is_some());
+            assert!(!buf.windows(6).any(|w| w == b"secret"));
+        }
+        assert!(matches!(
+            Outbox::load("sealed", None),
+            Err(StoreError::Locked)
+        ));
+        let mut outbox = Outbox::load("sealed", Some("pw")).unwrap();
+        assert_eq!(outbox.iter().map(|m| m.seq).collect::<Vec<_>>(), [1, 2]);
+        outbox.pop();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(home);
+    }
+}
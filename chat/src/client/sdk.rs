@@ -0,0 +1,379 @@
+use crate::{gunzip, gzip, Capability, Data, Encoding, Hello, Message, COMPRESS_THRESHOLD};
+use futures_util::{stream, Stream};
+use reqwest::{
+    header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE},
+    RequestBuilder, StatusCode,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    path::Path,
+};
+use tokio::time::{sleep, Duration};
+
+pub const SERVER: &str = "http://localhost:8000";
+const ONLINE_WITHIN: Duration = Duration::from_secs(5);
+const RECV_LIMIT: u64 = 16 << 20;
+
+#[derive(Debug)]
+pub enum SdkError {
+    Http(reqwest::Error),
+    Status(StatusCode),
+    Protocol(String),
+    Unsupported(Capability),
+    Io(std::io::Error),
+}
+impl SdkError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SdkError::Http(e) if e.is_connect() || e.is_timeout())
+    }
+}
+impl fmt::Display for SdkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SdkError::Http(e) => write!(f, "http: {e}"),
+            SdkError::Status(status) => write!(f, "server replied {status}"),
+            SdkError::Protocol(e) => write!(f, "protocol: {e}"),
+            SdkError::Unsupported(c) => write!(f, "server does not support {}", c.as_str()),
+            SdkError::Io(e) => write!(f, "io: {e}"),
+        }
+    }
+}
+impl Error for SdkError {}
+impl From<reqwest::Error> for SdkError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(status) => SdkError::Status(status),
+            None => SdkError::Http(e),
+        }
+    }
+}
+impl From<std::io::Error> for SdkError {
+    fn from(e: std::io::Error) -> Self {
+        SdkError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Presence {
+    pub id: u32,
+    pub last_seen: Option<Duration>,
+}
+impl Presence {
+    pub fn online(&self) -> bool {
+        self.last_seen.is_some_and(|seen| seen < ONLINE_WITHIN)
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    server: String,
+}
+impl Default for Client {
+    fn default() -> Self {
+        Self::new(std::env::var("CHAT_SERVER").unwrap_or_else(|_| SERVER.to_string()))
+    }
+}
+impl Client {
+    pub fn new(server: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .expect("can build client"),
+            server: server.into().trim_end_matches('/').to_string(),
+        }
+    }
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.server)
+    }
+    async fn call(request: RequestBuilder) -> Result<String, SdkError> {
+        Ok(request.send().await?.error_for_status()?.text().await?)
+    }
+    pub async fn login(&self) -> Result<u32, SdkError> {
+        let id = Self::call(self.http.get(self.url("/"))).await?;
+        id.parse()
+            .map_err(|_| SdkError::Protocol(format!("bad id {id:?}")))
+    }
+    pub async fn hello(&self, capabilities: &[Capability]) -> Result<Hello, SdkError> {
+        let resp = self
+            .http
+            .post(self.url("/hello"))
+            .json(&Hello::new(capabilities))
+            .send()
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(Hello::legacy(self.login().await?));
+        }
+        let hello = resp.error_for_status()?.json::<Hello>().await?;
+        match hello.id {
+            Some(_) => Ok(hello),
+            None => Err(SdkError::Protocol("hello without an id".to_string())),
+        }
+    }
+    pub async fn connect(self) -> Result<Session, SdkError> {
+        let hello = self.hello(Capability::ALL).await?;
+        Ok(Session {
+            client: self,
+            id: hello.id.unwrap_or_default(),
+            hello,
+        })
+    }
+    pub fn session(self, id: u32) -> Session {
+        Session {
+            client: self,
+            id,
+            hello: Hello {
+                encodings: vec![Encoding::Json],
+                id: Some(id),
+                ..Hello::new(Capability::ALL)
+            },
+        }
+    }
+    pub async fn resolve(&self, addr: &str) -> Result<u32, SdkError> {
+        let id = Client::call(
+            self.http
+                .get(self.url("/federation/resolve"))
+                .query(&[("addr", addr)]),
+        )
+        .await?;
+        id.parse()
+            .map_err(|_| SdkError::Protocol(format!("bad id {id:?}")))
+    }
+    pub async fn presence(&self, ids: &[u32]) -> Result<Vec<Presence>, SdkError> {
+        let query = ids.iter().map(|id| ("id", *id)).collect::<Vec<_>>();
+        let seen = self
+            .http
+            .get(self.url("/presence"))
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<HashMap<u32, u64>>()
+            .await?;
+        Ok(ids
+            .iter()
+            .map(|&id| Presence {
+                id,
+                last_seen: seen.get(&id).map(|&ms| Duration::from_millis(ms)),
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct Session {
+    client: Client,
+    id: u32,
+    hello: Hello,
+}
+impl Session {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn hello(&self) -> &Hello {
+        &self.hello
+    }
+    fn require(&self, capability: Capability) -> Result<(), SdkError> {
+        if self.hello.supports(capability) {
+            Ok(())
+        } else {
+            Err(SdkError::Unsupported(capability))
+        }
+    }
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+    pub async fn send(&self, dst: u32, data: Data, reply: Option<u64>) -> Result<u64, SdkError> {
+        if let Some(capability) = data.capability() {
+            self.require(capability)?;
+        }
+        let reply = reply.filter(|_| self.hello.supports(Capability::Replies));
+        let msg = Message::new(self.id, data).with_reply(reply);
+        let encoding = self.hello.encoding();
+        let client = &self.client;
+        let mut body = encoding.encode(&msg);
+        let mut req = client
+            .http
+            .post(client.url("/send"))
+            .query(&[("dst", dst)])
+            .header(CONTENT_TYPE, encoding.content_type());
+        if body.len() >= COMPRESS_THRESHOLD && self.hello.supports(Capability::Compression) {
+            let packed = gzip(&body);
+            if packed.len() < body.len() {
+                body = packed;
+                req = req.header(CONTENT_ENCODING, "gzip");
+            }
+        }
+        let mid = Client::call(req.body(body)).await?;
+        mid.parse()
+            .map_err(|_| SdkError::Protocol(format!("bad message id {mid:?}")))
+    }
+    pub async fn send_text(&self, dst: u32, text: impl Into<String>) -> Result<u64, SdkError> {
+        self.send(dst, Data::Text(text.into()), None).await
+    }
+    pub async fn send_file(&self, dst: u32, path: impl AsRef<Path>) -> Result<u64, SdkError> {
+        let path = path.as_ref();
+        let file = tokio::fs::read(path).await?;
+        let filename = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().to_string(),
+        );
+        self.send(dst, Data::File { filename, file }, None).await
+    }
+    pub async fn read(&self, mids: &[u64]) -> Result<(), SdkError> {
+        self.require(Capability::Receipts)?;
+        let client = &self.client;
+        Client::call(
+            client
+                .http
+                .post(client.url("/read"))
+                .query(&[("id", self.id)])
+                .json(mids),
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn join(&self, room: &str) -> Result<u32, SdkError> {
+        self.require(Capability::Rooms)?;
+        let client = &self.client;
+        let id = Client::call(
+            client
+                .http
+                .post(client.url("/join"))
+                .query(&[("id", self.id.to_string()), ("room", room.to_string())]),
+        )
+        .await?;
+        id.parse()
+            .map_err(|_| SdkError::Protocol(format!("bad room id {id:?}")))
+    }
+    pub async fn leave(&self, room: u32) -> Result<(), SdkError> {
+        self.require(Capability::Rooms)?;
+        let client = &self.client;
+        Client::call(
+            client
+                .http
+                .post(client.url("/leave"))
+                .query(&[("id", self.id), ("room", room)]),
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn recv(&self) -> Result<Vec<Message>, SdkError> {
+        let client = &self.client;
+        let mut req = client
+            .http
+            .get(client.url("/recv"))
+            .query(&[("dst", self.id)])
+            .header(ACCEPT, self.hello.encoding().content_type());
+        if self.hello.supports(Capability::Compression) {
+            req = req.header(ACCEPT_ENCODING, "gzip");
+        }
+        let resp = req.send().await?.error_for_status()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let encoding = header(CONTENT_TYPE)
+            .as_deref()
+            .map_or(Encoding::Json, Encoding::from_content_type);
+        let gzipped = header(CONTENT_ENCODING).is_some_and(|e| e.trim() == "gzip");
+        let mut buf = resp.bytes().await?.to_vec();
+        if gzipped {
+            buf = gunzip(&buf, RECV_LIMIT).map_err(SdkError::Protocol)?;
+        }
+        encoding.decode_each(&buf).map_err(SdkError::Protocol)
+    }
+    pub fn events(&self, every: Duration) -> impl Stream<Item = Result<Message, SdkError>> {
+        stream::unfold(
+            (self.clone(), VecDeque::new()),
+            move |(session, mut queue)| async move {
+                loop {
+                    if let Some(msg) = queue.pop_front() {
+                        return Some((Ok(msg), (session, queue)));
+                    }
+                    match session.recv().await {
+                        Ok(msgs) if msgs.is_empty() => sleep(every).await,
+                        Ok(msgs) => queue.extend(msgs),
+                        Err(e) => {
+                            sleep(every).await;
+                            return Some((Err(e), (session, queue)));
+                        }
+                    }
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    async fn serve(routes: &'static [(&'static str, &'static str, &'static str)]) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]);
+                let (status, body) = routes
+                    .iter()
+                    .find(|(line, ..)| head.starts_with(line))
+                    .map_or(("404 Not Found", ""), |(_, status, body)| (status, body));
+                let resp = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        Client::new(url)
+    }
+
+    #[tokio::test]
+    async fn hello_falls_back_to_legacy_login() {
+        let client = serve(&[("GET / ", "200 OK", "7")]).await;
+        let hello = client.hello(Capability::ALL).await.unwrap();
+        assert_eq!(hello.version, crate::LEGACY_VERSION);
+        assert_eq!(hello.id, Some(7));
+        assert_eq!(hello.capabilities, [Capability::Files]);
+        assert_eq!(hello.encoding(), Encoding::Json);
+    }
+
+    #[tokio::test]
+    async fn hello_uses_the_negotiated_reply() {
+        let client = serve(&[(
+            "POST /hello ",
+            "200 OK",
+            r#"{"version":2,"capabilities":["rooms"],"encodings":["message_pack"],"id":9}"#,
+        )])
+        .await;
+        let session = client.connect().await.unwrap();
+        assert_eq!(session.id(), 9);
+        assert!(session.hello().supports(Capability::Rooms));
+        assert_eq!(session.hello().encoding(), Encoding::MessagePack);
+    }
+
+    #[tokio::test]
+    async fn hello_needs_an_id() {
+        let client = serve(&[("POST /hello ", "200 OK", r#"{"version":2}"#)]).await;
+        let err = client.hello(Capability::ALL).await.err();
+        assert!(matches!(err, Some(SdkError::Protocol(_))));
+        let client = serve(&[("POST /hello ", "500 Internal Server Error", "")]).await;
+        let err = client.hello(Capability::ALL).await.err();
+        assert!(matches!(
+            err,
+            Some(SdkError::Status(StatusCode::INTERNAL_SERVER_ERROR))
+        ));
+    }
+}
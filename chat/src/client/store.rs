@@ -0,0 +1,131 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{error::Error, fmt, fs, path::PathBuf};
+
+const MAGIC: &[u8] = b"CHATENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    Locked,
+    BadPassphrase,
+}
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "history io: {e}"),
+            StoreError::Format(e) => write!(f, "history format: {e}"),
+            StoreError::Locked => write!(f, "history is encrypted, set CHAT_PASSPHRASE"),
+            StoreError::BadPassphrase => write!(f, "history passphrase is wrong"),
+        }
+    }
+}
+impl Error for StoreError {}
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Format(e)
+    }
+}
+
+pub struct Cipher {
+    salt: [u8; SALT_LEN],
+    cipher: ChaCha20Poly1305,
+}
+impl Cipher {
+    pub fn new(passphrase: &str, salt: [u8; SALT_LEN]) -> Self {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .expect("can derive key");
+        Self {
+            salt,
+            cipher: ChaCha20Poly1305::new(&key),
+        }
+    }
+    pub fn generate(passphrase: &str) -> Self {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::new(passphrase, salt)
+    }
+    pub fn salt(buf: &[u8]) -> Option<[u8; SALT_LEN]> {
+        if !buf.starts_with(MAGIC) || buf.len() < MAGIC.len() + SALT_LEN + NONCE_LEN {
+            return None;
+        }
+        buf[MAGIC.len()..][..SALT_LEN].try_into().ok()
+    }
+    pub fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self.cipher.encrypt(&nonce, plain).expect("can encrypt");
+        [MAGIC, &self.salt, &nonce, &sealed].concat()
+    }
+    pub fn open(&self, buf: &[u8]) -> Result<Vec<u8>, StoreError> {
+        let nonce = Nonce::from_slice(&buf[MAGIC.len() + SALT_LEN..][..NONCE_LEN]);
+        self.cipher
+            .decrypt(nonce, &buf[MAGIC.len() + SALT_LEN + NONCE_LEN..])
+            .map_err(|_| StoreError::BadPassphrase)
+    }
+}
+
+pub struct History {
+    path: PathBuf,
+    cipher: Option<Cipher>,
+}
+impl History {
+    pub fn open<T: serde::de::DeserializeOwned + Default>(
+        account: &str,
+        passphrase: Option<&str>,
+    ) -> Result<(Self, T), StoreError> {
+        let path = super::data_dir()
+            .join("history")
+            .join(format!("{account}.json"));
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let cipher = passphrase.map(Cipher::generate);
+                return Ok((Self { path, cipher }, T::default()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(salt) = Cipher::salt(&buf) {
+            let pass = passphrase.ok_or(StoreError::Locked)?;
+            let cipher = Cipher::new(pass, salt);
+            let data = serde_json::from_slice(&cipher.open(&buf)?)?;
+            Ok((
+                Self {
+                    path,
+                    cipher: Some(cipher),
+                },
+                data,
+            ))
+        } else {
+            let data = serde_json::from_slice(&buf)?;
+            let cipher = passphrase.map(Cipher::generate);
+            Ok((Self { path, cipher }, data))
+        }
+    }
+    pub fn save<T: serde::Serialize>(&self, data: &T) -> Result<(), StoreError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let plain = serde_json::to_vec(data)?;
+        let buf = match &self.cipher {
+            Some(cipher) => cipher.seal(&plain),
+            None => plain,
+        };
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use ratatui::style::Color;
+use std::{collections::HashMap, fs};
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub fg: Color,
+    pub bg: Color,
+    pub text: Color,
+    pub muted: Color,
+    pub accent: Color,
+    pub focus: Color,
+    pub notice: Color,
+    pub panel_fg: Color,
+    pub panel_bg: Color,
+    pub badge_fg: Color,
+    pub badge_bg: Color,
+    pub mark_fg: Color,
+    pub mark_bg: Color,
+    pub code_fg: Color,
+    pub code_bg: Color,
+    pub online_fg: Color,
+    pub online_bg: Color,
+    pub degraded_fg: Color,
+    pub degraded_bg: Color,
+    pub offline_fg: Color,
+    pub offline_bg: Color,
+    pub error: Color,
+}
+impl Theme {
+    pub fn light() -> Self {
+        Self {
+            fg: Color::Black,
+            bg: Color::White,
+            text: Color::Green,
+            muted: Color::DarkGray,
+            accent: Color::Blue,
+            focus: Color::Red,
+            notice: Color::Yellow,
+            panel_fg: Color::Cyan,
+            panel_bg: Color::Gray,
+            badge_fg: Color::White,
+            badge_bg: Color::Red,
+            mark_fg: Color::Black,
+            mark_bg: Color::Yellow,
+            code_fg: Color::Black,
+            code_bg: Color::Gray,
+            online_fg: Color::Black,
+            online_bg: Color::Green,
+            degraded_fg: Color::Black,
+            degraded_bg: Color::Yellow,
+            offline_fg: Color::White,
+            offline_bg: Color::Red,
+            error: Color::Red,
+        }
+    }
+    pub fn dark() -> Self {
+        Self {
+            fg: Color::Gray,
+            bg: Color::Black,
+            text: Color::LightGreen,
+            muted: Color::DarkGray,
+            accent: Color::LightBlue,
+            focus: Color::LightRed,
+            notice: Color::LightYellow,
+            panel_fg: Color::LightCyan,
+            panel_bg: Color::Rgb(30, 30, 30),
+            badge_fg: Color::Black,
+            badge_bg: Color::LightRed,
+            mark_fg: Color::Black,
+            mark_bg: Color::LightYellow,
+            code_fg: Color::White,
+            code_bg: Color::DarkGray,
+            online_fg: Color::Black,
+            online_bg: Color::Green,
+            degraded_fg: Color::Black,
+            degraded_bg: Color::Yellow,
+            offline_fg: Color::White,
+            offline_bg: Color::Red,
+            error: Color::Red,
+        }
+    }
+    pub fn high_contrast() -> Self {
+        Self {
+            fg: Color::White,
+            bg: Color::Black,
+            text: Color::White,
+            muted: Color::Gray,
+            accent: Color::LightCyan,
+            focus: Color::LightYellow,
+            notice: Color::LightYellow,
+            panel_fg: Color::White,
+            panel_bg: Color::Black,
+            badge_fg: Color::Black,
+            badge_bg: Color::LightYellow,
+            mark_fg: Color::Black,
+            mark_bg: Color::LightCyan,
+            code_fg: Color::Black,
+            code_bg: Color::White,
+            online_fg: Color::Black,
+            online_bg: Color::White,
+            degraded_fg: Color::Black,
+            degraded_bg: Color::LightYellow,
+            offline_fg: Color::White,
+            offline_bg: Color::Red,
+            error: Color::LightRed,
+        }
+    }
+    fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(Self::light()),
+            "dark" => Some(Self::dark()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let color = value
+            .parse()
+            .map_err(|_| format!("theme: bad color {value} for {key}"))?;
+        let slot = match key {
+            "fg" => &mut self.fg,
+            "bg" => &mut self.bg,
+            "text" => &mut self.text,
+            "muted" => &mut self.muted,
+            "accent" => &mut self.accent,
+            "focus" => &mut self.focus,
+            "notice" => &mut self.notice,
+            "panel_fg" => &mut self.panel_fg,
+            "panel_bg" => &mut self.panel_bg,
+            "badge_fg" => &mut self.badge_fg,
+            "badge_bg" => &mut self.badge_bg,
+            "mark_fg" => &mut self.mark_fg,
+            "mark_bg" => &mut self.mark_bg,
+            "code_fg" => &mut self.code_fg,
+            "code_bg" => &mut self.code_bg,
+            "online_fg" => &mut self.online_fg,
+            "online_bg" => &mut self.online_bg,
+            "degraded_fg" => &mut self.degraded_fg,
+            "degraded_bg" => &mut self.degraded_bg,
+            "offline_fg" => &mut self.offline_fg,
+            "offline_bg" => &mut self.offline_bg,
+            "error" => &mut self.error,
+            _ => return Err(format!("theme: unknown color {key}")),
+        };
+        *slot = color;
+        Ok(())
+    }
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some(theme) = Self::builtin(name) {
+            return Ok(theme);
+        }
+        let path = super::data_dir()
+            .join("themes")
+            .join(format!("{name}.json"));
+        let buf = fs::read(&path).map_err(|e| format!("theme {}: {e}", path.display()))?;
+        let mut colors = serde_json::from_slice::<HashMap<String, String>>(&buf)
+            .map_err(|e| format!("theme {}: {e}", path.display()))?;
+        let base = colors.remove("base").unwrap_or_else(|| "light".to_string());
+        let mut theme =
+            Self::builtin(&base).ok_or_else(|| format!("theme: unknown base theme {base}"))?;
+        for (key, value) in colors {
+            theme.set(&key, &value)?;
+        }
+        Ok(theme)
+    }
+    pub fn from_env() -> Result<Self, String> {
+        Self::load(std::env::var("CHAT_THEME").as_deref().unwrap_or("light"))
+    }
+}
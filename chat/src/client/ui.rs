@@ -1,155 +1,954 @@
-use super::action::Action;
-use crate::{Data, Message};
+use super::{
+    action::{Action, Request},
+    command::{self, Command, Input},
+    complete::{self, Completion},
+    composer::Composer,
+    conn::Status,
+    keymap::{Bind, Keymap},
+    markup::{self, Styled},
+    outbox::{Delivery, Outgoing},
+    store::History,
+    theme::Theme,
+    wrap::wrap,
+};
+use crate::{Capability, Data, Hello, Message, LEGACY_VERSION};
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen, SetTitle,
+    },
 };
 
 use ratatui::{
     Frame,
     {prelude::*, widgets::*},
 };
-use rocket::serde::json::Json;
 use std::{
-    collections::{BTreeMap, HashMap},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashMap},
     io::{stdout, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::mpsc as tokio_mpsc;
-use tui_input::{
-    backend::crossterm::{write as input_write, EventHandler},
-    Input,
-};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct Entry {
+    other: bool,
+    text: String,
+    seq: u64,
+    delivery: Option<Delivery>,
+    #[serde(default)]
+    mid: u64,
+    #[serde(default)]
+    seen: bool,
+    #[serde(default)]
+    edited: bool,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    reply: Option<u64>,
+    #[serde(default)]
+    reactions: BTreeMap<String, BTreeSet<u32>>,
+    #[serde(default)]
+    file: Option<PathBuf>,
+}
+impl Entry {
+    pub fn react(&mut self, emoji: String, from: u32, add: bool) {
+        let users = self.reactions.entry(emoji.clone()).or_default();
+        if add {
+            users.insert(from);
+        } else {
+            users.remove(&from);
+        }
+        if users.is_empty() {
+            self.reactions.remove(&emoji);
+        }
+    }
+}
+
+fn download(mid: u64, name: &str, file: &[u8]) -> std::io::Result<PathBuf> {
+    let dir = super::data_dir().join("downloads");
+    std::fs::create_dir_all(&dir)?;
+    for n in 0.. {
+        let path = match n {
+            0 => dir.join(name),
+            1 => dir.join(format!("{mid}-{name}")),
+            n => dir.join(format!("{mid}-{n}-{name}")),
+        };
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut out) => return out.write_all(file).map(|_| path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+enum Undo {
+    Amend {
+        mid: u64,
+        text: String,
+        edited: bool,
+        deleted: bool,
+    },
+    React {
+        mid: u64,
+        emoji: String,
+        from: u32,
+        add: bool,
+    },
+}
 
 #[derive(Clone, Debug)]
 struct Record {
     time_stamp: u32,
-    data: Vec<(bool, String)>,
+    data: Vec<Entry>,
+    unread: usize,
+    name: Option<String>,
 }
 impl Record {
     pub fn new(time_stamp: u32) -> Self {
         Self {
             time_stamp,
             data: vec![],
+            unread: 0,
+            name: None,
+        }
+    }
+    pub fn push(&mut self, mid: u64, reply: Option<u64>, data: String) -> &mut Entry {
+        self.data.push(Entry {
+            other: true,
+            text: data,
+            seq: 0,
+            delivery: None,
+            mid,
+            seen: false,
+            edited: false,
+            deleted: false,
+            reply,
+            reactions: BTreeMap::new(),
+            file: None,
+        });
+        self.data.last_mut().unwrap()
+    }
+    pub fn push_self(&mut self, seq: u64, reply: Option<u64>, data: String) -> &mut Entry {
+        self.data.push(Entry {
+            other: false,
+            text: data,
+            seq,
+            delivery: Some(Delivery::Pending),
+            mid: 0,
+            seen: true,
+            edited: false,
+            deleted: false,
+            reply,
+            reactions: BTreeMap::new(),
+            file: None,
+        });
+        self.data.last_mut().unwrap()
+    }
+    fn own(&mut self, f: impl Fn(&Entry) -> bool) -> Option<&mut Entry> {
+        self.data.iter_mut().rev().find(|e| !e.other && f(e))
+    }
+    pub fn deliver(&mut self, seq: u64, delivery: Delivery) {
+        if let Some(entry) = self.own(|e| e.seq == seq) {
+            entry.delivery = Some(entry.delivery.map_or(delivery, |d| d.upgrade(delivery)));
+        }
+    }
+    pub fn undo(&mut self, undo: Undo) {
+        match undo {
+            Undo::Amend {
+                mid,
+                text,
+                edited,
+                deleted,
+            } => {
+                if let Some(entry) = self.find(mid) {
+                    entry.text = text;
+                    entry.edited = edited;
+                    entry.deleted = deleted;
+                }
+            }
+            Undo::React {
+                mid,
+                emoji,
+                from,
+                add,
+            } => {
+                if let Some(entry) = self.find(mid) {
+                    entry.react(emoji, from, !add);
+                }
+            }
+        }
+    }
+    pub fn apply(&mut self, data: &Data) -> Option<Undo> {
+        let (Data::Edit { mid, .. } | Data::Delete { mid } | Data::Reaction { mid, .. }) = *data
+        else {
+            return None;
+        };
+        let entry = self.find(mid)?;
+        if let Data::Reaction {
+            emoji, from, add, ..
+        } = data
+        {
+            entry.react(emoji.clone(), *from, *add);
+            return Some(Undo::React {
+                mid,
+                emoji: emoji.clone(),
+                from: *from,
+                add: *add,
+            });
+        }
+        let undo = Undo::Amend {
+            mid,
+            text: entry.text.clone(),
+            edited: entry.edited,
+            deleted: entry.deleted,
+        };
+        match data {
+            Data::Edit { text, .. } => {
+                entry.text = text.clone();
+                entry.edited = true;
+            }
+            _ => {
+                entry.text = "message deleted".to_string();
+                entry.edited = false;
+                entry.deleted = true;
+            }
+        }
+        Some(undo)
+    }
+    pub fn sent(&mut self, seq: u64, mid: u64) {
+        if let Some(entry) = self.own(|e| e.seq == seq) {
+            entry.mid = mid;
+        }
+        self.deliver(seq, Delivery::Sent);
+    }
+    pub fn find(&mut self, mid: u64) -> Option<&mut Entry> {
+        self.data.iter_mut().rev().find(|e| e.mid == mid)
+    }
+    pub fn target(&mut self, picked: Option<usize>) -> Option<&mut Entry> {
+        match picked {
+            Some(i) if self.data.get(i).is_some_and(|e| !e.other && !e.deleted) => {
+                self.data.get_mut(i)
+            }
+            _ => self.own(|e| !e.deleted),
+        }
+    }
+    pub fn receipt(&mut self, mid: u64, read: bool) {
+        if let Some(entry) = self.own(|e| e.mid == mid) {
+            let delivery = if read {
+                Delivery::Read
+            } else {
+                Delivery::Delivered
+            };
+            entry.delivery = Some(entry.delivery.map_or(delivery, |d| d.upgrade(delivery)));
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    id: u32,
+    data: Vec<Entry>,
+    #[serde(default)]
+    unread: usize,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notify {
+    Off,
+    Bell,
+    Title,
+    Both,
+}
+impl Notify {
+    pub fn from_env() -> Self {
+        match std::env::var("CHAT_NOTIFY").as_deref() {
+            Ok("off") => Notify::Off,
+            Ok("bell") => Notify::Bell,
+            Ok("both") => Notify::Both,
+            _ => Notify::Title,
         }
     }
-    pub fn push(&mut self, data: String) {
-        self.data.push((true, data));
+    pub fn bell(self) -> bool {
+        matches!(self, Notify::Bell | Notify::Both)
     }
-    pub fn push_self(&mut self, data: String) {
-        self.data.push((false, data));
+    pub fn title(self) -> bool {
+        matches!(self, Notify::Title | Notify::Both)
     }
 }
 
 pub struct Ui {
     state: State,
+    history: Option<History>,
+    notify: Notify,
+    keymap: Keymap,
+    unread: usize,
+    dirty: bool,
+    saved_at: Instant,
+    quit: bool,
     pub rx: tokio_mpsc::Receiver<Action>,
-    pub tx: tokio_mpsc::Sender<Json<Message>>,
+    pub tx: tokio_mpsc::Sender<Request>,
 }
 impl Ui {
-    pub fn new(
-        id: u32,
-        rx: tokio_mpsc::Receiver<Action>,
-        tx: tokio_mpsc::Sender<Json<Message>>,
-    ) -> Self {
+    pub fn new(rx: tokio_mpsc::Receiver<Action>, tx: tokio_mpsc::Sender<Request>) -> Self {
+        let mut state = State::new();
+        let mut notices = vec![];
+        match Theme::from_env() {
+            Ok(theme) => state.theme = theme,
+            Err(e) => notices.push(e),
+        }
+        let keymap = Keymap::load().unwrap_or_else(|e| {
+            notices.push(e);
+            Keymap::new()
+        });
+        state.notice = notices.join("; ");
         Self {
-            state: State::new(id),
+            state,
+            history: None,
+            notify: Notify::from_env(),
+            keymap,
+            unread: usize::MAX,
+            dirty: false,
+            saved_at: Instant::now(),
+            quit: false,
             rx,
             tx,
         }
     }
+    async fn submit(&mut self) {
+        match command::parse(self.state.input.value()) {
+            Ok(Input::Text(text)) if text.is_empty() => {}
+            Ok(Input::Text(text)) => {
+                if self.state.selected == 0 {
+                    self.state.notice = "no conversation open, try /open <id>".to_string();
+                    return;
+                }
+                self.send(Data::Text(text)).await;
+            }
+            Ok(Input::Command(command)) => self.execute(command).await,
+            Err(e) => {
+                self.state.notice = e;
+                return;
+            }
+        }
+        self.state.input.reset();
+    }
+    async fn execute(&mut self, command: Command) {
+        let selected = self.state.selected;
+        match command {
+            Command::Help(name) => self.state.notice = command::help(name.as_deref()),
+            Command::Open(id) => {
+                self.state.list.update(id);
+                self.state.select(id);
+            }
+            Command::Resolve(addr) => {
+                self.state.notice = format!("looking up {addr}");
+                self.request(Request::Resolve(addr)).await;
+            }
+            Command::Join(_) if self.state.unsupported(Capability::Rooms) => {}
+            Command::Join(room) => {
+                self.state.notice = format!("joining {room}");
+                self.request(Request::Join(room)).await;
+            }
+            Command::Quit => self.quit = true,
+            _ if selected == 0 => {
+                self.state.notice = "no conversation open, try /open <id>".to_string();
+            }
+            Command::File(_) if self.state.unsupported(Capability::Files) => {}
+            Command::File(path) => {
+                let local = complete::expand(&path);
+                match tokio::fs::read(&local).await {
+                    Ok(file) => {
+                        let filename = local
+                            .file_name()
+                            .map_or(path.clone(), |name| name.to_string_lossy().to_string());
+                        self.send(Data::File { filename, file }).await;
+                        if let Some(entry) = self.state.list.update(selected).data.last_mut() {
+                            entry.file = std::fs::canonicalize(&local).ok();
+                        }
+                    }
+                    Err(e) => self.state.notice = format!("can't read {path}: {e}"),
+                }
+            }
+            Command::Nick(name) => {
+                if let Some(record) = self.state.list.by_id.get_mut(&selected) {
+                    record.name = Some(name);
+                }
+            }
+            Command::Leave => {
+                if self.state.supports(Capability::Rooms) {
+                    self.request(Request::Leave(selected)).await;
+                }
+                self.state.list.remove(selected);
+                self.state.select(0);
+            }
+            Command::Edit(text) => self.amend(|mid| Data::Edit { mid, text }).await,
+            Command::Delete => self.amend(|mid| Data::Delete { mid }).await,
+            Command::React(emoji) => self.react(emoji).await,
+            Command::Clear => {
+                if let Some(record) = self.state.list.by_id.get_mut(&selected) {
+                    record.data.clear();
+                }
+            }
+        }
+    }
+    fn complete(&mut self) {
+        let input = &mut self.state.input;
+        if let Some(completion) = &mut self.state.completion {
+            let index = completion
+                .index
+                .map_or(0, |i| (i + 1) % completion.candidates.len());
+            let candidate = &completion.candidates[index];
+            input.replace(completion.start..completion.end, candidate);
+            completion.end = completion.start + candidate.len();
+            completion.index = Some(index);
+            return;
+        }
+        let list = self
+            .state
+            .list
+            .by_id
+            .iter()
+            .filter(|(id, _)| **id != 0)
+            .map(|(id, record)| (*id, record.name.clone()))
+            .collect::<Vec<_>>();
+        let (start, mut candidates) = complete::candidates(input.value(), input.cursor(), &list);
+        candidates.dedup();
+        match candidates.len() {
+            0 => self.state.notice = "no completions".to_string(),
+            1 => {
+                let mut candidate = candidates.remove(0);
+                if !candidate.ends_with('/') {
+                    candidate.push(' ');
+                }
+                input.replace(start..input.cursor(), &candidate);
+            }
+            _ => {
+                let common = complete::common_prefix(&candidates);
+                input.replace(start..input.cursor(), &common);
+                self.state.completion = Some(Completion {
+                    start,
+                    end: start + common.len(),
+                    candidates,
+                    index: None,
+                });
+            }
+        }
+    }
+    async fn receive(&mut self, msg: Message) {
+        self.state.err = format!("recv {:?}", msg.to_string());
+        if msg.data.is_event() {
+            let (Data::Receipt { mid, .. }
+            | Data::Edit { mid, .. }
+            | Data::Delete { mid }
+            | Data::Reaction { mid, .. }) = msg.data
+            else {
+                return;
+            };
+            let Some(record) = self
+                .state
+                .mids
+                .get(&mid)
+                .and_then(|id| self.state.list.by_id.get_mut(id))
+            else {
+                return;
+            };
+            match msg.data {
+                Data::Receipt { mid, read } => record.receipt(mid, read),
+                Data::Edit { mid, text } => {
+                    if let Some(entry) = record.find(mid) {
+                        entry.text = format!("{}: {}", msg.id, text);
+                        entry.edited = true;
+                    }
+                }
+                Data::Delete { mid } => {
+                    if let Some(entry) = record.find(mid) {
+                        entry.text = "message deleted".to_string();
+                        entry.edited = false;
+                        entry.deleted = true;
+                    }
+                }
+                Data::Reaction {
+                    mid,
+                    emoji,
+                    from,
+                    add,
+                } => {
+                    if let Some(entry) = record.find(mid) {
+                        entry.react(emoji, from, add);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        if msg.mid != 0 {
+            self.state.mids.insert(msg.mid, msg.id);
+        }
+        let text = msg.to_string();
+        let file = match msg.data {
+            Data::File { filename, file } => {
+                let name = Path::new(&filename)
+                    .file_name()
+                    .map_or("download".to_string(), |name| {
+                        name.to_string_lossy().to_string()
+                    });
+                download(msg.mid, &name, &file)
+                    .map_err(|e| self.state.notice = format!("can't save {name}: {e}"))
+                    .ok()
+            }
+            _ => None,
+        };
+        let record = self.state.list.update(msg.id);
+        record.push(msg.mid, msg.reply, text).file = file;
+        if msg.id != self.state.selected {
+            record.unread += 1;
+            if self.notify.bell() {
+                let _ = stdout().write_all(b"\x07").and_then(|_| stdout().flush());
+            }
+        } else if self.state.scroll > 0 {
+            self.state.scroll += 1;
+            self.state.unseen += 1;
+        }
+    }
+    async fn mark_read(&mut self) {
+        let Some(record) = self.state.list.by_id.get_mut(&self.state.selected) else {
+            return;
+        };
+        let mids = record
+            .data
+            .iter_mut()
+            .filter(|e| e.other && !e.seen && e.mid != 0)
+            .map(|e| {
+                e.seen = true;
+                e.mid
+            })
+            .collect::<Vec<_>>();
+        if !mids.is_empty() && self.state.supports(Capability::Receipts) {
+            self.request(Request::Read(mids)).await;
+        }
+    }
+    async fn amend(&mut self, data: impl FnOnce(u64) -> Data) {
+        if self.state.unsupported(Capability::Edits) {
+            return;
+        }
+        let dst = self.state.selected;
+        let picked = self.state.picked;
+        let Some(record) = self.state.list.by_id.get_mut(&dst) else {
+            self.state.notice = "nothing to change".to_string();
+            return;
+        };
+        let Some(mid) = record.target(picked).map(|entry| entry.mid) else {
+            self.state.notice = "nothing to change".to_string();
+            return;
+        };
+        if mid == 0 {
+            self.state.notice = "message is not sent yet".to_string();
+            return;
+        }
+        let data = data(mid);
+        let undo = record.apply(&data).expect("can amend target");
+        self.state.seq += 1;
+        let seq = self.state.seq;
+        self.state.pending.insert(seq, dst);
+        self.state.undo.insert(seq, undo);
+        self.request(Request::Send(Outgoing::new(seq, dst, data)))
+            .await;
+    }
+    async fn key(&mut self, key: KeyEvent) {
+        let page = self.state.page.get().max(1) as isize;
+        let newline = key
+            .modifiers
+            .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT);
+        if !self.keymap.is(&key, Bind::Complete) {
+            self.state.completion = None;
+        }
+        let typing = self.state.focus == Focus::Input
+            && matches!(key.code, KeyCode::Char(_))
+            && !key
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let is = |bind| !typing && self.keymap.is(&key, bind);
+        let history = self.state.focus == Focus::History;
+        match (self.state.focus, key.code) {
+            _ if is(Bind::Quit) => self.quit = true,
+            (Focus::Input, _) if is(Bind::Complete) => self.complete(),
+            _ if is(Bind::FocusNext) => self.state.focus = self.state.focus.next(),
+            _ if is(Bind::FocusPrevious) => self.state.focus = self.state.focus.previous(),
+            _ if is(Bind::PageUp) => self.state.scroll_by(page),
+            _ if is(Bind::PageDown) => self.state.scroll_by(-page),
+            _ if history && is(Bind::Up) => self.state.pick_by(-1),
+            _ if history && is(Bind::Down) => self.state.pick_by(1),
+            _ if history && is(Bind::Top) => self.state.scroll_by(isize::MAX),
+            _ if history && is(Bind::Bottom) => {
+                self.state.picked = None;
+                self.state.scroll_by(isize::MIN);
+            }
+            _ if history && is(Bind::Reply) => self.reply(),
+            _ if history && is(Bind::React) => self.react("+1".to_string()).await,
+            (Focus::Contacts, KeyCode::Enter) => self.state.focus = Focus::Input,
+            (Focus::Input, KeyCode::Enter) if !newline => self.submit().await,
+            (Focus::Input, KeyCode::Backspace)
+                if self.state.input.value().is_empty() && self.state.reply.is_some() =>
+            {
+                self.state.reply = None;
+            }
+            _ if is(Bind::Down) => self.state.select(self.state.list.next(self.state.selected)),
+            _ if is(Bind::Up) => self
+                .state
+                .select(self.state.list.previous(self.state.selected)),
+            (Focus::Input, _) => {
+                self.state.input.handle(key);
+            }
+            _ => {}
+        }
+    }
+    fn mouse(&mut self, mouse: MouseEvent) {
+        let (x, y) = (mouse.column, mouse.row);
+        let inside = |area: Rect| {
+            (area.x..area.x + area.width).contains(&x)
+                && (area.y..area.y + area.height).contains(&y)
+        };
+        let contacts = self.state.contacts_area.get();
+        let chat = self.state.chat_area.get();
+        let last = self.state.list.rank.len().saturating_sub(1);
+        match mouse.kind {
+            MouseEventKind::ScrollUp if inside(chat) => self.state.scroll_by(3),
+            MouseEventKind::ScrollDown if inside(chat) => self.state.scroll_by(-3),
+            MouseEventKind::ScrollUp if inside(contacts) => {
+                self.state.contacts_offset = self.state.contacts_offset.saturating_sub(1);
+            }
+            MouseEventKind::ScrollDown if inside(contacts) => {
+                self.state.contacts_offset = (self.state.contacts_offset + 1).min(last);
+            }
+            MouseEventKind::Down(MouseButton::Left) if inside(contacts) => {
+                let row = (y - contacts.y) as usize;
+                let Some(&id) = row.checked_sub(1).and_then(|row| {
+                    self.state
+                        .list
+                        .rank
+                        .values()
+                        .rev()
+                        .nth(row + self.state.contacts_offset)
+                }) else {
+                    return;
+                };
+                self.state.select(id);
+                self.state.focus = Focus::Input;
+            }
+            MouseEventKind::Down(MouseButton::Left) if inside(chat) => {
+                let mut top = chat.y + 1;
+                let rows = self.state.rows.borrow().clone();
+                for (i, height) in rows {
+                    if (top..top + height).contains(&y) {
+                        self.state.picked = Some(i);
+                        self.state.focus = Focus::History;
+                        self.open(i);
+                        break;
+                    }
+                    top += height;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn open(&mut self, i: usize) {
+        let Some(entry) = self
+            .state
+            .list
+            .by_id
+            .get(&self.state.selected)
+            .and_then(|record| record.data.get(i))
+        else {
+            return;
+        };
+        let Some(path) = entry.file.clone() else {
+            return;
+        };
+        if !path.exists() {
+            self.state.notice = format!("{} is gone", path.display());
+            return;
+        }
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else if cfg!(windows) {
+            "explorer"
+        } else {
+            "xdg-open"
+        };
+        self.state.notice = match tokio::process::Command::new(opener)
+            .arg(&path)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        {
+            Ok(_) => format!("opened {}", path.display()),
+            Err(_) => format!("saved at {}", path.display()),
+        };
+    }
+    async fn react(&mut self, emoji: String) {
+        if self.state.unsupported(Capability::Reactions) {
+            return;
+        }
+        let emoji = match emoji.as_str() {
+            "+1" => "👍",
+            "-1" => "👎",
+            "heart" => "❤️",
+            "laugh" => "😂",
+            "tada" => "🎉",
+            emoji => emoji,
+        }
+        .to_string();
+        let (dst, me, picked) = (self.state.selected, self.state.id, self.state.picked);
+        let Some(record) = self.state.list.by_id.get_mut(&dst) else {
+            self.state.notice = "nothing to react to".to_string();
+            return;
+        };
+        let Some(entry) = picked
+            .or(record.data.len().checked_sub(1))
+            .and_then(|i| record.data.get(i))
+        else {
+            self.state.notice = "nothing to react to".to_string();
+            return;
+        };
+        if entry.mid == 0 {
+            self.state.notice = "message is not sent yet".to_string();
+            return;
+        }
+        let add = !entry
+            .reactions
+            .get(&emoji)
+            .is_some_and(|users| users.contains(&me));
+        let data = Data::Reaction {
+            mid: entry.mid,
+            emoji,
+            from: me,
+            add,
+        };
+        let undo = record.apply(&data).expect("can react to target");
+        self.state.seq += 1;
+        let seq = self.state.seq;
+        self.state.pending.insert(seq, dst);
+        self.state.undo.insert(seq, undo);
+        self.request(Request::Send(Outgoing::new(seq, dst, data)))
+            .await;
+    }
+    fn reply(&mut self) {
+        if self.state.unsupported(Capability::Replies) {
+            return;
+        }
+        let Some(entry) = self
+            .state
+            .picked
+            .and_then(|i| self.state.list.by_id.get(&self.state.selected)?.data.get(i))
+        else {
+            return;
+        };
+        if entry.mid == 0 {
+            self.state.notice = "message is not sent yet".to_string();
+            return;
+        }
+        self.state.reply = Some(entry.mid);
+        self.state.picked = None;
+        self.state.focus = Focus::Input;
+    }
+    async fn request(&mut self, request: Request) {
+        self.tx.send(request).await.expect("can send");
+    }
+    async fn send(&mut self, data: Data) {
+        let dst = self.state.selected;
+        self.state.scroll_by(isize::MIN);
+        self.state.seq += 1;
+        let seq = self.state.seq;
+        let reply = self.state.reply.take();
+        self.state
+            .list
+            .update(dst)
+            .push_self(seq, reply, data.to_string());
+        self.state.pending.insert(seq, dst);
+        let mut msg = Outgoing::new(seq, dst, data);
+        msg.reply = reply;
+        self.request(Request::Send(msg)).await;
+    }
+    fn load_history(&mut self) {
+        let passphrase = std::env::var("CHAT_PASSPHRASE").ok();
+        match History::open::<Vec<Saved>>(&super::account(), passphrase.as_deref()) {
+            Ok((history, saved)) => {
+                for Saved {
+                    id,
+                    data,
+                    unread,
+                    name,
+                } in saved
+                {
+                    for entry in data.iter().filter(|e| e.mid != 0) {
+                        self.state.mids.insert(entry.mid, id);
+                    }
+                    let record = self.state.list.update(id);
+                    record.data = data;
+                    record.unread = unread;
+                    record.name = name;
+                }
+                self.history = Some(history);
+            }
+            Err(e) => self.state.notice = e.to_string(),
+        }
+    }
+    fn save_history(&mut self, force: bool) {
+        if !self.dirty || (!force && self.saved_at.elapsed() < Duration::from_secs(2)) {
+            return;
+        }
+        if let Some(history) = &self.history {
+            let saved = self
+                .state
+                .list
+                .rank
+                .values()
+                .filter(|id| **id != 0)
+                .map(|id| Saved {
+                    id: *id,
+                    data: self.state.list.by_id[id]
+                        .data
+                        .iter()
+                        .filter(|e| e.delivery != Some(Delivery::Pending))
+                        .cloned()
+                        .collect(),
+                    unread: self.state.list.by_id[id].unread,
+                    name: self.state.list.by_id[id].name.clone(),
+                })
+                .collect::<Vec<_>>();
+            if let Err(e) = history.save(&saved) {
+                self.state.notice = e.to_string();
+            }
+        }
+        self.dirty = false;
+        self.saved_at = Instant::now();
+    }
+    fn update_title(&mut self) {
+        let unread = self.state.list.by_id.values().map(|r| r.unread).sum();
+        if !self.notify.title() || unread == self.unread {
+            return;
+        }
+        self.unread = unread;
+        let title = if unread == 0 {
+            "chat".to_string()
+        } else {
+            format!("chat ({unread})")
+        };
+        let _ = execute!(stdout(), SetTitle(title));
+    }
     pub async fn run(&mut self) {
+        self.load_history();
         enable_raw_mode().expect("can run in raw mode");
         // ?;
-        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture).expect("can run in raw mode");
+        execute!(stdout(), EnterAlternateScreen).expect("can run in raw mode");
+        let mouse = std::env::var("CHAT_MOUSE").map_or(true, |v| v != "off");
+        if mouse {
+            execute!(stdout(), EnableMouseCapture).expect("can run in raw mode");
+        }
+        let enhanced = supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+            )
+            .expect("can run in raw mode");
+        }
         // ?;
         let backend = CrosstermBackend::new(stdout());
         let mut terminal = Terminal::new(backend).unwrap();
         // ?;
-        loop {
+        while !self.quit {
+            self.mark_read().await;
+            self.update_title();
             terminal.draw(|f| ui(f, &self.state)).expect("can draw");
-            match self.rx.recv().await.expect("can recv") {
-                Action::Receive(msg) => {
-                    self.state.err = format!("recv {:?}", msg.to_string());
-                    self.state.list.update(msg.id).push(msg.to_string());
-                    if let Data::File { filename, file } = msg.data {
-                        tokio::fs::write(filename, file).await.expect("can write");
+            self.save_history(false);
+            let action = self.rx.recv().await.expect("can recv");
+            if !matches!(action, Action::Status(_)) {
+                self.dirty = true;
+            }
+            match action {
+                Action::Receive(msg) => self.receive(msg).await,
+                Action::Event(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    self.key(key).await
+                }
+                Action::Event(Event::Mouse(mouse)) => self.mouse(mouse),
+                Action::Event(_) => {}
+                Action::Login(hello) => {
+                    let id = hello.id.unwrap_or_default();
+                    if self.state.id != 0 && self.state.id != id {
+                        self.state.notice = format!("the server restarted, you are now {id}");
+                    }
+                    self.state.id = id;
+                    if hello.version == LEGACY_VERSION {
+                        self.state.notice =
+                            "server speaks the legacy protocol, some features are off".to_string();
+                    }
+                    self.state.hello = Some(hello);
+                }
+                Action::Status(status) => {
+                    self.state.status = status;
+                }
+                Action::Queued(msg) => {
+                    let record = self.state.list.update(msg.dst);
+                    if !msg.data.is_event() {
+                        record.push_self(msg.seq, msg.reply, msg.data.to_string());
+                    } else if let Some(undo) = record.apply(&msg.data) {
+                        self.state.undo.insert(msg.seq, undo);
+                    }
+                    self.state.pending.insert(msg.seq, msg.dst);
+                }
+                Action::Sent(seq, mid) => {
+                    self.state.undo.remove(&seq);
+                    if let Some(dst) = self.state.pending.remove(&seq) {
+                        if let Some(record) = self.state.list.by_id.get_mut(&dst) {
+                            record.sent(seq, mid);
+                            self.state.mids.insert(mid, dst);
+                        }
                     }
                 }
-                Action::Event(event) => {
-                    if let Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code,
-                        ..
-                    }) = event
-                    {
-                        match code {
-                            KeyCode::Enter => {
-                                let str = self.state.input.value().to_string();
-                                if str.starts_with("\\") {
-                                    if str.starts_with("\\f:") {
-                                        if let Ok(content) =
-                                            tokio::fs::read_to_string(&str[3..]).await
-                                        {
-                                            let msg = Message::new(
-                                                self.state.selected,
-                                                Data::File {
-                                                    filename: str[3..]
-                                                        .rsplit_once('/')
-                                                        .unwrap_or(("", &str[3..]))
-                                                        .1
-                                                        .to_string(),
-                                                    file: content.into_bytes(),
-                                                },
-                                            );
-                                            self.state
-                                                .list
-                                                .update(self.state.selected)
-                                                .push_self(msg.data.to_string());
-                                            self.tx.send(Json::from(msg)).await.expect("can send");
-                                        }
-                                    } else {
-                                        let _ = str[1..].parse::<u32>().map(|id| {
-                                            self.state.list.update(id);
-                                            self.state.selected = id;
-                                        });
+                Action::Delivery(seq, delivery) => {
+                    let undo = self.state.undo.remove(&seq);
+                    if let Some(dst) = self.state.pending.remove(&seq) {
+                        if let Some(record) = self.state.list.by_id.get_mut(&dst) {
+                            match undo {
+                                Some(undo) if delivery == Delivery::Failed => {
+                                    self.state.notice = match undo {
+                                        Undo::Amend { .. } => "the server rejected the change",
+                                        Undo::React { .. } => "the server rejected the reaction",
                                     }
-                                } else if self.state.selected != 0 {
-                                    self.state
-                                        .list
-                                        .update(self.state.selected)
-                                        .push_self(str.clone());
-                                    let msg =
-                                        Message::new(self.state.selected, Data::Text(str.clone()));
-                                    self.tx.send(Json::from(msg)).await.expect("can send");
-                                }
-                                self.state.input.reset();
-                            }
-                            KeyCode::Down => {
-                                self.state.selected = self.state.list.next(self.state.selected);
-                            }
-                            KeyCode::Up => {
-                                self.state.selected = self.state.list.previous(self.state.selected);
-                            }
-                            _ => {
-                                if self.state.input.handle_event(&event).is_some() {
-                                    stdout().flush().expect("can flush");
+                                    .to_string();
+                                    record.undo(undo);
                                 }
+                                _ => record.deliver(seq, delivery),
                             }
                         }
                     }
                 }
-                Action::Over => {
-                    disable_raw_mode().expect("can run in raw mode");
-                    execute!(
-                        terminal.backend_mut(),
-                        LeaveAlternateScreen,
-                        DisableMouseCapture
-                    )
-                    .expect("can run in raw mode");
-                    terminal.show_cursor().expect("can run in raw mode");
-                    // ?;
-                    break;
+                Action::Joined(id, room) => {
+                    let record = self.state.list.update(id);
+                    record.name.get_or_insert(room);
+                    self.state.select(id);
+                    self.state.notice.clear();
+                }
+                Action::Notice(notice) => {
+                    self.state.notice = notice;
                 }
                 #[cfg(debug_assertions)]
                 Action::Err(err) => {
@@ -157,6 +956,41 @@ impl Ui {
                 }
             };
         }
+        self.save_history(true);
+        if enhanced {
+            execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)
+                .expect("can run in raw mode");
+        }
+        disable_raw_mode().expect("can run in raw mode");
+        if mouse {
+            execute!(terminal.backend_mut(), DisableMouseCapture).expect("can run in raw mode");
+        }
+        execute!(terminal.backend_mut(), LeaveAlternateScreen).expect("can run in raw mode");
+        terminal.show_cursor().expect("can run in raw mode");
+        // ?;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Contacts,
+    History,
+    Input,
+}
+impl Focus {
+    pub fn next(self) -> Self {
+        match self {
+            Focus::Contacts => Focus::History,
+            Focus::History => Focus::Input,
+            Focus::Input => Focus::Contacts,
+        }
+    }
+    pub fn previous(self) -> Self {
+        match self {
+            Focus::Contacts => Focus::Input,
+            Focus::History => Focus::Contacts,
+            Focus::Input => Focus::History,
+        }
     }
 }
 
@@ -164,8 +998,29 @@ struct State {
     pub id: u32,
     pub list: LazyList,
     pub selected: u32,
-    pub input: Input,
+    pub input: Composer,
+    pub completion: Option<Completion>,
+    pub focus: Focus,
+    pub scroll: usize,
+    pub unseen: usize,
+    pub page: Cell<usize>,
+    pub max_scroll: Cell<usize>,
+    pub first: Cell<usize>,
+    pub contacts_area: Cell<Rect>,
+    pub chat_area: Cell<Rect>,
+    pub rows: RefCell<Vec<(usize, u16)>>,
+    pub contacts_offset: usize,
+    pub picked: Option<usize>,
+    pub reply: Option<u64>,
+    pub status: Status,
+    pub seq: u64,
+    pub pending: HashMap<u64, u32>,
+    pub undo: HashMap<u64, Undo>,
+    pub mids: HashMap<u64, u32>,
+    pub notice: String,
     pub err: String,
+    pub theme: Theme,
+    pub hello: Option<Hello>,
 }
 
 struct LazyList {
@@ -181,7 +1036,7 @@ impl LazyList {
             by_id: HashMap::from([(0, Record::new(0))]),
         }
     }
-    pub fn next(&mut self, id: u32) -> u32 {
+    pub fn next(&self, id: u32) -> u32 {
         let record = self.by_id.get(&id).unwrap();
         let (_, id) = self
             .rank
@@ -190,7 +1045,7 @@ impl LazyList {
             .unwrap_or(self.rank.last_key_value().unwrap_or((&0, &0)));
         *id
     }
-    pub fn previous(&mut self, id: u32) -> u32 {
+    pub fn previous(&self, id: u32) -> u32 {
         let record = self.by_id.get(&id).unwrap();
         let (_, id) = self
             .rank
@@ -199,65 +1054,169 @@ impl LazyList {
             .unwrap_or(self.rank.first_key_value().unwrap_or((&0, &0)));
         *id
     }
+    pub fn remove(&mut self, id: u32) {
+        if let Some(record) = self.by_id.remove(&id) {
+            self.rank.remove(&record.time_stamp);
+        }
+    }
     pub fn update(&mut self, id: u32) -> &mut Record {
-        self.time_stamp = self.time_stamp + 1;
-        match self.by_id.get_mut(&id) {
-            Some(record) => {
-                self.rank.remove(&record.time_stamp);
-                record.time_stamp = self.time_stamp;
-            }
-            None => {}
+        self.time_stamp += 1;
+        if let Some(record) = self.by_id.get_mut(&id) {
+            self.rank.remove(&record.time_stamp);
+            record.time_stamp = self.time_stamp;
         }
         self.rank.insert(self.time_stamp, id);
         self.by_id.entry(id).or_insert(Record::new(self.time_stamp))
     }
 }
 impl State {
-    pub fn new(id: u32) -> Self {
+    pub fn select(&mut self, id: u32) {
+        if let Some(record) = self.list.by_id.get_mut(&id) {
+            record.unread = 0;
+        }
+        self.selected = id;
+        self.scroll = 0;
+        self.unseen = 0;
+        self.picked = None;
+        self.reply = None;
+    }
+    pub fn pick_by(&mut self, delta: isize) {
+        let len = self
+            .list
+            .by_id
+            .get(&self.selected)
+            .map_or(0, |record| record.data.len());
+        let end = len - self.scroll.min(self.max_scroll.get()).min(len);
+        if len == 0 {
+            return;
+        }
+        let Some(picked) = self.picked else {
+            self.picked = Some(end.saturating_sub(1));
+            return;
+        };
+        let picked = picked.saturating_add_signed(delta);
+        if picked >= len {
+            self.picked = None;
+            return;
+        }
+        if picked < self.first.get() {
+            self.scroll_by(1);
+        } else if picked >= end {
+            self.scroll_by(-1);
+        }
+        self.picked = Some(picked);
+    }
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.hello
+            .as_ref()
+            .is_none_or(|hello| hello.supports(capability))
+    }
+    pub fn unsupported(&mut self, capability: Capability) -> bool {
+        if self.supports(capability) {
+            return false;
+        }
+        self.notice = format!("server does not support {}", capability.as_str());
+        true
+    }
+    pub fn scroll_by(&mut self, delta: isize) {
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(delta)
+            .min(self.max_scroll.get());
+        if self.scroll == 0 {
+            self.unseen = 0;
+        }
+    }
+    pub fn new() -> Self {
         Self {
-            id,
+            id: 0,
             list: LazyList::new(),
             selected: 0,
-            input: Input::new("".to_string()),
+            input: Composer::new(),
+            completion: None,
+            focus: Focus::Input,
+            scroll: 0,
+            unseen: 0,
+            page: Cell::new(0),
+            max_scroll: Cell::new(0),
+            first: Cell::new(0),
+            contacts_area: Cell::new(Rect::default()),
+            chat_area: Cell::new(Rect::default()),
+            rows: RefCell::new(vec![]),
+            contacts_offset: 0,
+            picked: None,
+            reply: None,
+            status: Status::Connecting,
+            seq: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or_default(),
+            pending: HashMap::new(),
+            undo: HashMap::new(),
+            mids: HashMap::new(),
+            notice: "".to_string(),
             err: "".to_string(),
+            theme: Theme::light(),
+            hello: None,
         }
     }
 }
 
+fn border(app: &State, focus: Focus) -> Style {
+    if app.focus == focus {
+        Style::default().fg(app.theme.focus)
+    } else {
+        Style::default()
+    }
+}
+
 fn ui(f: &mut Frame, app: &State) {
-    #[allow(unused_mut)]
-    let mut size = f.size();
+    let theme = &app.theme;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(f.size());
+    let size = chunks[0];
+    let status = format!(" {} ", app.status.as_str());
+    let mut bar = vec![match app.status {
+        Status::Online => status.fg(theme.online_fg).bg(theme.online_bg),
+        Status::Connecting | Status::Degraded => status.fg(theme.degraded_fg).bg(theme.degraded_bg),
+        Status::Offline => status.fg(theme.offline_fg).bg(theme.offline_bg),
+    }];
+    bar.push(format!(" {}", app.notice).fg(theme.notice));
     #[cfg(debug_assertions)]
-    {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0), Constraint::Length(1)])
-            .split(size);
-        let err = Paragraph::new(Line::from(vec!["Error".red(), app.err.clone().gray()]))
-            .style(Style::default().fg(Color::Red));
-        f.render_widget(err, chunks[1]);
-
-        size = chunks[0];
-    }
-    let block = Block::default().on_white().black();
+    bar.extend([" Error".fg(theme.error), app.err.clone().fg(theme.muted)]);
+    f.render_widget(Paragraph::new(Line::from(bar)), chunks[1]);
+    let block = Block::default().bg(theme.bg).fg(theme.fg);
     f.render_widget(block.clone(), size);
 
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Length(5), Constraint::Min(0)])
+        .constraints([Constraint::Length(14), Constraint::Min(0)])
         .split(size);
     let tabs: Vec<ListItem> = app
         .list
         .rank
         .iter()
         .rev()
+        .skip(app.contacts_offset)
         .map(|(_, id)| {
-            let id = if *id == app.selected {
-                id.to_string().red()
+            let record = &app.list.by_id[id];
+            let unread = record.unread;
+            let name = record.name.clone().unwrap_or_else(|| id.to_string());
+            let name = if *id == app.selected {
+                name.fg(theme.focus)
+            } else if unread > 0 {
+                name.fg(theme.fg).bold()
             } else {
-                id.to_string().green()
+                name.fg(theme.text)
             };
-            ListItem::new(id)
+            let mut spans = vec![name];
+            if unread > 0 {
+                spans.push(" ".into());
+                spans.push(unread.to_string().fg(theme.badge_fg).bg(theme.badge_bg));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect();
     let list = List::new(tabs)
@@ -265,53 +1224,299 @@ fn ui(f: &mut Frame, app: &State) {
             Block::default()
                 .borders(Borders::ALL)
                 .title("Who")
-                .border_type(BorderType::Rounded),
+                .border_type(BorderType::Rounded)
+                .border_style(border(app, Focus::Contacts)),
         )
-        .style(Style::default().cyan().on_gray());
+        .style(Style::default().fg(theme.panel_fg).bg(theme.panel_bg));
     f.render_widget(list, chunks[0]);
+    app.contacts_area.set(chunks[0]);
+    let (lines, (x, y)) = app.input.layout(chunks[1].width.saturating_sub(6) as usize);
+    let rows = lines.len().clamp(1, 5);
+    let replying = app.reply.map(|mid| {
+        let data = app
+            .list
+            .by_id
+            .get(&app.selected)
+            .map_or(&[][..], |record| &record.data[..]);
+        snippet(
+            mid,
+            data,
+            chunks[1].width.saturating_sub(14) as usize,
+            theme,
+        )
+    });
+    let top = y.saturating_sub(rows - 1);
     let sub_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(1), Constraint::Length(3)])
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(rows as u16 + 2 + replying.is_some() as u16),
+        ])
         .split(chunks[1]);
-    let cells = if app.selected == 0 {
-        vec![]
+    let page = sub_chunks[0].height.saturating_sub(2) as usize;
+    app.page.set(page);
+    let width = sub_chunks[0].width.saturating_sub(3) as usize;
+    let data = match app.list.by_id.get(&app.selected) {
+        Some(record) if app.selected != 0 => &record.data[..],
+        _ => &[],
+    };
+    let mut room = page;
+    let mut first = 0;
+    let me = [app.id.to_string(), super::account()];
+    while first < data.len() && bubble_height(&data[first], width, theme, &me) <= room {
+        room -= bubble_height(&data[first], width, theme, &me);
+        first += 1;
+    }
+    let max_scroll = data.len().saturating_sub(first.max(1));
+    app.max_scroll.set(max_scroll);
+    let end = data.len() - app.scroll.min(max_scroll);
+    let mut room = if app.unseen > 0 {
+        page.saturating_sub(1)
     } else {
-        let data = &app.list.by_id.get(&app.selected).unwrap().data;
-        let max = sub_chunks[0].height.saturating_sub(2) as usize;
-        data.iter()
-            .skip(data.len().saturating_sub(max))
-            .map(|(other, str)| {
-                Row::new(vec![Line::from(str.clone().green()).alignment(if *other {
-                    Alignment::Left
-                } else {
-                    Alignment::Right
-                })])
-            })
-            .collect::<Vec<_>>()
+        page
     };
+    let mut cells = vec![];
+    let mut rows = vec![];
+    for (i, entry) in data[..end].iter().enumerate().rev() {
+        let height = bubble_height(entry, width, theme, &me);
+        if height > room && !cells.is_empty() {
+            break;
+        }
+        room = room.saturating_sub(height);
+        rows.push((i, height as u16));
+        cells.push(bubble(
+            entry,
+            width,
+            data,
+            app.focus == Focus::History && app.picked == Some(i),
+            theme,
+            &me,
+        ));
+    }
+    app.first.set(end - cells.len());
+    cells.reverse();
+    rows.reverse();
+    app.rows.replace(rows);
+    app.chat_area.set(sub_chunks[0]);
+    if app.unseen > 0 {
+        cells.push(Row::new(vec![Line::from(
+            format!("↓ {} new message(s) below", app.unseen)
+                .fg(theme.mark_fg)
+                .bg(theme.mark_bg),
+        )
+        .alignment(Alignment::Center)]));
+    }
 
     let chat = Table::new(cells)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!("[{:03}]", app.selected))
-                .border_type(BorderType::Rounded),
+                .title(format!(
+                    "[{:03}] {}",
+                    app.selected,
+                    app.list
+                        .by_id
+                        .get(&app.selected)
+                        .and_then(|record| record.name.as_deref())
+                        .unwrap_or("")
+                ))
+                .border_type(BorderType::Rounded)
+                .border_style(border(app, Focus::History)),
         )
         .widths(&[Constraint::Percentage(100)]);
     f.render_widget(chat, sub_chunks[0]);
+    if max_scroll > 0 {
+        let mut scrollbar =
+            ScrollbarState::new(max_scroll).position(max_scroll - app.scroll.min(max_scroll));
+        f.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None),
+            sub_chunks[0].inner(&Margin {
+                vertical: 1,
+                horizontal: 0,
+            }),
+            &mut scrollbar,
+        );
+    }
 
-    let _ = input_write(
-        &mut stdout(),
-        app.input.value(),
-        app.input.cursor(),
-        (sub_chunks[1].x + 5, sub_chunks[1].y + 1),
-        sub_chunks[1].width.saturating_sub(6),
-    );
-    let input = Paragraph::new(format!("{:03}>", app.id,)).block(
-        Block::new()
-            .borders(Borders::ALL)
-            .border_type(BorderType::Rounded),
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(border(app, Focus::Input));
+    let mut inner = block.inner(sub_chunks[1]);
+    f.render_widget(block, sub_chunks[1]);
+    if let Some(replying) = replying {
+        f.render_widget(
+            Paragraph::new(Line::from(vec![
+                "↪ reply to ".fg(theme.muted),
+                replying.italic().fg(theme.muted),
+            ])),
+            inner,
+        );
+        inner.y += 1;
+        inner.height = inner.height.saturating_sub(1);
+    }
+    f.render_widget(Paragraph::new(format!("{:03}>", app.id)), inner);
+    let text = Rect {
+        x: inner.x + 4,
+        width: inner.width.saturating_sub(4),
+        ..inner
+    };
+    f.render_widget(
+        Paragraph::new(
+            lines[top..]
+                .iter()
+                .map(|line| Line::from(line.as_str()))
+                .collect::<Vec<_>>(),
+        ),
+        text,
     );
+    if app.focus == Focus::Input {
+        f.set_cursor(text.x + x as u16, text.y + (y - top) as u16);
+    }
+    if let Some(completion) = &app.completion {
+        let height = completion.candidates.len().min(8) as u16 + 2;
+        let width = completion
+            .candidates
+            .iter()
+            .map(|candidate| candidate.chars().count())
+            .max()
+            .unwrap_or(0) as u16
+            + 2;
+        let area = Rect {
+            x: text.x,
+            y: sub_chunks[1].y.saturating_sub(height),
+            width: width.min(text.width),
+            height: height.min(sub_chunks[1].y),
+        };
+        let items = completion
+            .candidates
+            .iter()
+            .map(|candidate| ListItem::new(candidate.as_str()))
+            .collect::<Vec<_>>();
+        let mut state = ListState::default().with_selected(completion.index);
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(
+            List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded),
+                )
+                .style(Style::default().fg(theme.code_fg).bg(theme.code_bg))
+                .highlight_style(Style::default().fg(theme.bg).bg(theme.accent)),
+            area,
+            &mut state,
+        );
+    }
+}
+
+fn suffix(entry: &Entry, theme: &Theme) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    if entry.edited {
+        spans.push(" (edited)".fg(theme.muted));
+    }
+    match entry.delivery {
+        Some(Delivery::Pending) => spans.push(" ◷".fg(theme.muted)),
+        Some(Delivery::Sent) => spans.push(" ✓".fg(theme.muted)),
+        Some(Delivery::Delivered) => spans.push(" ✓✓".fg(theme.muted)),
+        Some(Delivery::Read) => spans.push(" ✓✓".fg(theme.accent)),
+        Some(Delivery::Failed) => spans.push(" ✗".fg(theme.error)),
+        None => {}
+    }
+    spans
+}
+
+fn bubble_lines(
+    entry: &Entry,
+    width: usize,
+    style: Style,
+    theme: &Theme,
+    me: &[String],
+) -> Vec<Line<'static>> {
+    let suffix = suffix(entry, theme)
+        .iter()
+        .map(|span| span.width())
+        .sum::<usize>();
+    let styled = if entry.deleted {
+        Styled::plain(&entry.text, style)
+    } else {
+        markup::parse(&entry.text, style, theme, me)
+    };
+    styled.lines(width.saturating_sub(suffix))
+}
+
+fn snippet(mid: u64, data: &[Entry], width: usize, theme: &Theme) -> String {
+    let Some(entry) = data.iter().find(|e| e.mid == mid) else {
+        return "message unavailable".to_string();
+    };
+    let text = markup::parse(&entry.text, Style::default(), theme, &[]).text;
+    let lines = wrap(&text, width.saturating_sub(1));
+    match &lines[..] {
+        [line] => line.clone(),
+        [line, ..] => format!("{line}…"),
+        [] => String::new(),
+    }
+}
+
+fn bubble_height(entry: &Entry, width: usize, theme: &Theme, me: &[String]) -> usize {
+    bubble_lines(entry, width, Style::default(), theme, me).len()
+        + entry.reply.is_some() as usize
+        + !entry.reactions.is_empty() as usize
+}
 
-    f.render_widget(input, sub_chunks[1]);
+fn bubble(
+    entry: &Entry,
+    width: usize,
+    data: &[Entry],
+    picked: bool,
+    theme: &Theme,
+    me: &[String],
+) -> Row<'static> {
+    let alignment = if entry.other {
+        Alignment::Left
+    } else {
+        Alignment::Right
+    };
+    let mut style = if entry.deleted {
+        Style::default().fg(theme.muted).italic()
+    } else {
+        Style::default().fg(theme.text)
+    };
+    if picked {
+        style = style.reversed();
+    }
+    let mut lines = vec![];
+    if let Some(mid) = entry.reply {
+        lines.push(
+            Line::from(vec![
+                "┃ ".fg(theme.muted),
+                snippet(mid, data, width.saturating_sub(2), theme).fg(theme.muted),
+            ])
+            .alignment(alignment),
+        );
+    }
+    lines.extend(
+        bubble_lines(entry, width, style, theme, me)
+            .into_iter()
+            .map(|line| line.alignment(alignment)),
+    );
+    if let Some(last) = lines.last_mut() {
+        last.spans.extend(suffix(entry, theme));
+    }
+    if !entry.reactions.is_empty() {
+        let mut spans = vec![];
+        for (emoji, users) in &entry.reactions {
+            spans.push(
+                format!(" {emoji} {} ", users.len())
+                    .fg(theme.code_fg)
+                    .bg(theme.code_bg),
+            );
+            spans.push(" ".into());
+        }
+        lines.push(Line::from(spans).alignment(alignment));
+    }
+    let height = lines.len() as u16;
+    Row::new(vec![Text::from(lines)]).height(height)
 }
@@ -0,0 +1,80 @@
+use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+pub fn wrap_ranges(text: &str, width: usize) -> Vec<Range<usize>> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    let mut offset = 0;
+    for para in text.split('\n') {
+        let (mut start, mut end, mut pos) = (offset, offset, offset);
+        let mut line_width = 0;
+        for word in para.split_inclusive(' ') {
+            let trimmed = word.trim_end();
+            let word_width = trimmed.width();
+            if line_width > 0 && line_width + word_width > width {
+                lines.push(start..end);
+                (start, end) = (pos, pos);
+                line_width = 0;
+            }
+            if word_width > width {
+                for (i, c) in word.char_indices() {
+                    let w = c.width().unwrap_or(0);
+                    if line_width > 0 && line_width + w > width {
+                        lines.push(start..pos + i);
+                        start = pos + i;
+                        line_width = 0;
+                    }
+                    line_width += w;
+                }
+            } else {
+                line_width += word.width();
+            }
+            if !trimmed.is_empty() {
+                end = pos + trimmed.len();
+            }
+            pos += word.len();
+        }
+        lines.push(start..end.max(start));
+        offset += para.len() + 1;
+    }
+    lines
+}
+
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    wrap_ranges(text, width)
+        .into_iter()
+        .map(|range| text[range].to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_at_word_boundaries() {
+        assert_eq!(wrap("hello big world", 9), vec!["hello big", "world"]);
+        assert_eq!(wrap("a\n\nb", 5), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(wrap("abcdefgh ij", 3), vec!["abc", "def", "gh", "ij"]);
+    }
+
+    #[test]
+    fn counts_display_width() {
+        assert_eq!(wrap("你好世界", 4), vec!["你好", "世界"]);
+        assert_eq!(wrap("你好世界", 5), vec!["你好", "世界"]);
+        assert_eq!(wrap("🙂🙂🙂 ok", 6), vec!["🙂🙂🙂", "ok"]);
+    }
+
+    #[test]
+    fn ranges_index_the_original_text() {
+        let text = "héllo wörld\nnext";
+        for range in wrap_ranges(text, 5) {
+            assert!(text.is_char_boundary(range.start) && text.is_char_boundary(range.end));
+        }
+        assert_eq!(wrap_ranges("", 10), vec![0..0]);
+    }
+}
@@ -1,15 +1,69 @@
 pub mod client;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use std::{
+    fmt,
+    io::{Read, Write},
+};
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub enum Data {
     Text(String),
-    File { filename: String, file: Vec<u8> },
+    File {
+        filename: String,
+        #[serde(with = "serde_bytes")]
+        file: Vec<u8>,
+    },
+    Receipt {
+        mid: u64,
+        read: bool,
+    },
+    Edit {
+        mid: u64,
+        text: String,
+    },
+    Delete {
+        mid: u64,
+    },
+    Reaction {
+        mid: u64,
+        emoji: String,
+        from: u32,
+        add: bool,
+    },
 }
-impl ToString for Data {
-    fn to_string(&self) -> String {
+impl Data {
+    pub fn is_event(&self) -> bool {
+        !matches!(self, Data::Text(_) | Data::File { .. })
+    }
+    pub fn capability(&self) -> Option<Capability> {
         match self {
-            Data::Text(s) => s.clone(),
+            Data::Text(_) => None,
+            Data::File { .. } => Some(Capability::Files),
+            Data::Receipt { .. } => Some(Capability::Receipts),
+            Data::Edit { .. } | Data::Delete { .. } => Some(Capability::Edits),
+            Data::Reaction { .. } => Some(Capability::Reactions),
+        }
+    }
+}
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Data::Text(s) => write!(f, "{s}"),
             Data::File { filename, file } => {
-                format!("file{{name: {filename}, size: {}}}", file.len())
+                write!(f, "file{{name: {filename}, size: {}}}", file.len())
+            }
+            Data::Receipt { mid, read } => {
+                write!(f, "receipt{{mid: {mid}, read: {read}}}")
+            }
+            Data::Edit { mid, text } => write!(f, "edit{{mid: {mid}}}: {text}"),
+            Data::Delete { mid } => write!(f, "delete{{mid: {mid}}}"),
+            Data::Reaction {
+                mid,
+                emoji,
+                from,
+                add,
+            } => {
+                let op = if *add { "+" } else { "-" };
+                write!(f, "reaction{{mid: {mid}, from: {from}}}: {op}{emoji}")
             }
         }
     }
@@ -17,15 +71,332 @@ impl ToString for Data {
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct Message {
     pub id: u32,
+    #[serde(default)]
+    pub mid: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<u64>,
     pub data: Data,
 }
 impl Message {
     pub fn new(id: u32, data: Data) -> Self {
-        Self { id, data }
+        Self {
+            id,
+            mid: 0,
+            reply: None,
+            data,
+        }
+    }
+    pub fn with_reply(mut self, reply: Option<u64>) -> Self {
+        self.reply = reply;
+        self
+    }
+}
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.id, self.data)
+    }
+}
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const LEGACY_VERSION: u32 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Files,
+    Rooms,
+    Receipts,
+    Edits,
+    Reactions,
+    Replies,
+    Compression,
+    #[serde(other)]
+    Unknown,
+}
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::Files,
+        Capability::Rooms,
+        Capability::Receipts,
+        Capability::Edits,
+        Capability::Reactions,
+        Capability::Replies,
+        Capability::Compression,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Files => "files",
+            Capability::Rooms => "rooms",
+            Capability::Receipts => "receipts",
+            Capability::Edits => "edits",
+            Capability::Reactions => "reactions",
+            Capability::Replies => "replies",
+            Capability::Compression => "compression",
+            Capability::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    #[serde(other)]
+    Unknown,
+}
+impl Encoding {
+    pub const PREFERRED: &'static [Encoding] = &[Encoding::MessagePack, Encoding::Json];
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::MessagePack => "application/msgpack",
+            _ => "application/json",
+        }
+    }
+    pub fn from_content_type(content_type: &str) -> Self {
+        if content_type.contains("msgpack") {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
+    pub fn encode<T: serde::Serialize>(&self, value: &T) -> Vec<u8> {
+        match self {
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).expect("can encode"),
+            _ => serde_json::to_vec(value).expect("can encode"),
+        }
+    }
+    pub fn decode<T: serde::de::DeserializeOwned>(&self, buf: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::MessagePack => rmp_serde::from_slice(buf).map_err(|e| e.to_string()),
+            _ => serde_json::from_slice(buf).map_err(|e| e.to_string()),
+        }
+    }
+    pub fn decode_each<T: serde::de::DeserializeOwned>(
+        &self,
+        buf: &[u8],
+    ) -> Result<Vec<T>, String> {
+        Ok(self
+            .decode::<Vec<serde_value::Value>>(buf)?
+            .into_iter()
+            .filter_map(|value| value.deserialize_into().ok())
+            .collect())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Hello {
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+}
+impl Hello {
+    pub fn new(capabilities: &[Capability]) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.to_vec(),
+            encodings: Encoding::PREFERRED.to_vec(),
+            id: None,
+        }
     }
+    pub fn legacy(id: u32) -> Self {
+        Self {
+            version: LEGACY_VERSION,
+            capabilities: vec![Capability::Files],
+            encodings: vec![Encoding::Json],
+            id: Some(id),
+        }
+    }
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+    pub fn encoding(&self) -> Encoding {
+        self.encodings.first().copied().unwrap_or(Encoding::Json)
+    }
+    pub fn negotiate(&self, other: &Hello) -> Hello {
+        Hello {
+            version: self.version.min(other.version),
+            capabilities: self
+                .capabilities
+                .iter()
+                .filter(|c| **c != Capability::Unknown && other.supports(**c))
+                .copied()
+                .collect(),
+            encodings: vec![other
+                .encodings
+                .iter()
+                .find(|e| **e != Encoding::Unknown && self.encodings.contains(e))
+                .copied()
+                .unwrap_or(Encoding::Json)],
+            id: None,
+        }
+    }
+}
+
+pub const COMPRESS_THRESHOLD: usize = 1024;
+
+pub fn gzip(buf: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(buf).expect("can compress");
+    encoder.finish().expect("can compress")
 }
-impl ToString for Message {
-    fn to_string(&self) -> String {
-        return format!("{}: {}", self.id, self.data.to_string());
+
+pub fn gunzip(buf: &[u8], limit: u64) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    GzDecoder::new(buf)
+        .take(limit + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| e.to_string())?;
+    if out.len() as u64 > limit {
+        return Err("decompressed payload is too large".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_keeps_shared_capabilities() {
+        let server = Hello::new(Capability::ALL);
+        let client = serde_json::from_str::<Hello>(
+            r#"{"version":7,"capabilities":["files","teleport","replies"],"encodings":["json"]}"#,
+        )
+        .unwrap();
+        assert!(client.supports(Capability::Unknown));
+        let agreed = server.negotiate(&client);
+        assert_eq!(agreed.version, PROTOCOL_VERSION);
+        assert_eq!(
+            agreed.capabilities,
+            [Capability::Files, Capability::Replies]
+        );
+        assert_eq!(agreed.encodings, [Encoding::Json]);
+        assert_eq!(agreed.id, None);
+    }
+
+    #[test]
+    fn negotiate_prefers_the_clients_encoding() {
+        let server = Hello::new(&[Capability::Rooms]);
+        let mut client = Hello::new(&[Capability::Files]);
+        client.version = LEGACY_VERSION;
+        let agreed = server.negotiate(&client);
+        assert_eq!(agreed.version, LEGACY_VERSION);
+        assert!(agreed.capabilities.is_empty());
+        assert_eq!(agreed.encoding(), Encoding::MessagePack);
+        client.encodings = vec![Encoding::Json, Encoding::MessagePack];
+        assert_eq!(server.negotiate(&client).encoding(), Encoding::Json);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_json() {
+        let mut server = Hello::new(Capability::ALL);
+        server.encodings = vec![Encoding::Json];
+        let client =
+            serde_json::from_str::<Hello>(r#"{"version":2,"encodings":["cbor","message_pack"]}"#)
+                .unwrap();
+        assert_eq!(client.encodings, [Encoding::Unknown, Encoding::MessagePack]);
+        let agreed = server.negotiate(&client);
+        assert_eq!(agreed.encodings, [Encoding::Json]);
+        assert!(agreed.capabilities.is_empty());
+        let bare = serde_json::from_str::<Hello>(r#"{"version":2}"#).unwrap();
+        assert_eq!(bare.encoding(), Encoding::Json);
+    }
+
+    #[derive(serde::Serialize)]
+    enum FutureData {
+        Text(String),
+        Hologram { depth: u32 },
+    }
+
+    #[derive(serde::Serialize)]
+    struct FutureMessage {
+        id: u32,
+        data: FutureData,
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::new(1, Data::Text("hi".to_string())),
+            Message::new(
+                2,
+                Data::File {
+                    filename: "a.bin".to_string(),
+                    file: vec![0, 159, 255],
+                },
+            )
+            .with_reply(Some(5)),
+            Message::new(3, Data::Delete { mid: 4 }),
+        ]
+    }
+
+    #[test]
+    fn round_trip_both_encodings() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let buf = encoding.encode(&messages());
+            let decoded = encoding.decode_each::<Message>(&buf).unwrap();
+            assert_eq!(
+                decoded.iter().map(Message::to_string).collect::<Vec<_>>(),
+                messages()
+                    .iter()
+                    .map(Message::to_string)
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(decoded[1].reply, Some(5));
+            assert!(matches!(&decoded[1].data, Data::File { file, .. } if file == &[0, 159, 255]));
+        }
+    }
+
+    #[test]
+    fn unknown_variants_are_skipped() {
+        let future = [
+            FutureMessage {
+                id: 1,
+                data: FutureData::Text("old".to_string()),
+            },
+            FutureMessage {
+                id: 2,
+                data: FutureData::Hologram { depth: 3 },
+            },
+            FutureMessage {
+                id: 3,
+                data: FutureData::Text("new".to_string()),
+            },
+        ];
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let buf = encoding.encode(&future);
+            assert!(encoding.decode::<Vec<Message>>(&buf).is_err());
+            let decoded = encoding.decode_each::<Message>(&buf).unwrap();
+            assert_eq!(
+                decoded.iter().map(|m| m.id).collect::<Vec<_>>(),
+                [1, 3],
+                "{encoding:?}"
+            );
+        }
+        assert!(Encoding::Json.decode_each::<Message>(b"{").is_err());
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let buf = b"hello hello hello hello".repeat(100);
+        let packed = gzip(&buf);
+        assert!(packed.len() < buf.len());
+        assert_eq!(gunzip(&packed, buf.len() as u64).unwrap(), buf);
+        assert_eq!(gunzip(&gzip(b""), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn gunzip_enforces_the_limit() {
+        let bomb = gzip(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 4096);
+        assert_eq!(gunzip(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+        let err = gunzip(&bomb, (1 << 20) - 1).unwrap_err();
+        assert_eq!(err, "decompressed payload is too large");
+        assert!(gunzip(&bomb, 0).is_err());
+        assert!(gunzip(b"not gzip", 1 << 20).is_err());
     }
 }
@@ -0,0 +1,119 @@
+use chat::{
+    client::sdk::{Client, SdkError, Session},
+    Data,
+};
+use reqwest::StatusCode;
+use std::{
+    fs,
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+use tokio::time::{sleep, Duration};
+
+struct Server(Child);
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+async fn start(dir: &Path, name: &str, port: u16, peer: (&str, u16, &str)) -> (Server, String) {
+    let (peer, peer_port, trust) = peer;
+    let config = dir.join(format!("{name}.json"));
+    fs::write(
+        &config,
+        format!(
+            r#"{{"name":"{name}","peers":{{"{peer}":{{"url":"http://127.0.0.1:{peer_port}","secret":"s3cret","trust":"{trust}"}}}}}}"#
+        ),
+    )
+    .expect("can write config");
+    let url = format!("http://127.0.0.1:{port}");
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_server"))
+            .env("ROCKET_ADDRESS", "127.0.0.1")
+            .env("ROCKET_PORT", port.to_string())
+            .env("CHAT_FEDERATION", &config)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("can start server"),
+    );
+    for _ in 0..100 {
+        if reqwest::get(format!("{url}/metrics")).await.is_ok() {
+            return (server, url);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{name} did not start");
+}
+
+async fn texts(session: &Session) -> Vec<(u32, String)> {
+    session
+        .recv()
+        .await
+        .expect("can recv")
+        .into_iter()
+        .filter_map(|msg| match msg.data {
+            Data::Text(text) => Some((msg.id, text)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn relay_round_trip() {
+    let dir = std::env::temp_dir().join(format!("chat-federation-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("can create dir");
+    let base = 20000 + (std::process::id() % 20000) as u16;
+    let (alpha_port, beta_port) = (base, base + 1);
+    let (_alpha, alpha) = start(&dir, "alpha", alpha_port, ("beta", beta_port, "files")).await;
+    let (_beta, beta) = start(&dir, "beta", beta_port, ("alpha", alpha_port, "text")).await;
+
+    let ann = Client::new(alpha)
+        .connect()
+        .await
+        .expect("can connect to alpha");
+    let bob = Client::new(beta)
+        .connect()
+        .await
+        .expect("can connect to beta");
+
+    let to_bob = ann
+        .client()
+        .resolve(&format!("{}@beta", bob.id()))
+        .await
+        .expect("can resolve bob");
+    ann.send_text(to_bob, "hello bob").await.expect("can relay");
+    let received = texts(&bob).await;
+    assert_eq!(received.len(), 1);
+    let (from_ann, text) = &received[0];
+    assert_eq!(text, "hello bob");
+
+    let to_ann = bob
+        .client()
+        .resolve(&format!("{}@alpha", ann.id()))
+        .await
+        .expect("can resolve ann");
+    assert_eq!(*from_ann, to_ann);
+    bob.send_text(to_ann, "hi ann")
+        .await
+        .expect("can relay back");
+    assert_eq!(texts(&ann).await, vec![(to_bob, "hi ann".to_string())]);
+
+    let err = bob
+        .send(
+            to_ann,
+            Data::File {
+                filename: "a.txt".to_string(),
+                file: vec![1, 2, 3],
+            },
+            None,
+        )
+        .await
+        .err();
+    assert!(matches!(err, Some(SdkError::Status(StatusCode::FORBIDDEN))));
+    let err = ann.client().resolve("2@nowhere").await.err();
+    assert!(matches!(err, Some(SdkError::Status(StatusCode::NOT_FOUND))));
+    let _ = fs::remove_dir_all(&dir);
+}